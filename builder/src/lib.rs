@@ -2,10 +2,15 @@ extern crate proc_macro;
 
 use proc_macro::TokenStream;
 
+use proc_macro2::{Delimiter, Group, Spacing, Span, TokenStream as TS, TokenTree};
 use quote::{quote, ToTokens};
+use syn::parse::{Parse, ParseStream, Parser};
+use syn::punctuated::Punctuated;
 use syn::{
-    parse_macro_input, Data, DeriveInput, Field, Fields, GenericArgument, Ident, Lit, Meta,
-    NestedMeta, PathArguments, Type,
+    parse_macro_input, parse_quote, Attribute, Data, DeriveInput, Expr, Field, Fields, FieldsNamed,
+    FnArg, GenericArgument, GenericParam, Generics, Ident, ImplItem, ImplItemMethod, Index, ItemFn,
+    ItemImpl, Lifetime, LifetimeDef, Lit, LitStr, Member, Meta, MetaList, MetaNameValue,
+    NestedMeta, Pat, PathArguments, ReturnType, Token, Type, TypeParam, Visibility,
 };
 
 fn simple_inner_type<'a>(ty: &'a Type, wrapper: &'static str) -> Option<&'a Type> {
@@ -20,7 +25,7 @@ fn simple_inner_type<'a>(ty: &'a Type, wrapper: &'static str) -> Option<&'a Type
         return None;
     }
     let seg = &path.path.segments[0];
-    if &seg.ident.to_string() != wrapper {
+    if seg.ident != wrapper {
         return None;
     }
     let angles = match &seg.arguments {
@@ -46,202 +51,3634 @@ fn optional_type(ty: &Type) -> &Type {
     simple_inner_type(ty, "Option").expect("Expected optional field")
 }
 
-fn builder_error<T: ToTokens>(att: T) -> TokenStream {
+// The item types of a collection used with `each`, guessed from its name and
+// generic arguments.  A type named `...Map` with two type arguments is filled
+// from a key and a value, any other collection from its first type argument.
+// `each(name = "...", item = "...")` or `each(..., key = "...", value = "...")`
+// gives them when the guess is wrong.
+fn collection_items(ty: &Type) -> Option<Vec<Type>> {
+    let seg = match ty {
+        Type::Path(path) if path.qself.is_none() => path.path.segments.last()?.into_value(),
+        _ => return None,
+    };
+    let angles = match &seg.arguments {
+        PathArguments::AngleBracketed(angles) => angles,
+        _ => return None,
+    };
+    let types: Vec<&Type> = angles
+        .args
+        .iter()
+        .filter_map(|arg| match arg {
+            GenericArgument::Type(ty) => Some(ty),
+            _ => None,
+        })
+        .collect();
+    if seg.ident.to_string().ends_with("Map") && types.len() >= 2 {
+        Some(types[..2].iter().map(|ty| (*ty).clone()).collect())
+    } else {
+        types.first().map(|ty| vec![(*ty).clone()])
+    }
+}
+
+fn each_error<T: ToTokens>(tokens: T) -> syn::Error {
+    syn::Error::new_spanned(
+        tokens,
+        "expected `each = \"...\"` or `each(name = \"...\", item = \"...\")`, with `key = \"...\", value = \"...\"` in place of `item` for a map",
+    )
+}
+
+// `each(name = "...", item = "...")`, or with a `key` and a `value` for a map
+fn each_opts(ml: &MetaList) -> syn::Result<Each> {
+    let mut name = None;
+    let (mut item, mut key, mut value) = (None, None, None);
+    for nested in ml.nested.iter() {
+        match nested {
+            NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+                ident,
+                lit: Lit::Str(s),
+                ..
+            })) => match ident.to_string().as_str() {
+                "name" => name = Some(parse_str::<Ident>(s)?),
+                "item" => item = Some(parse_str::<Type>(s)?),
+                "key" => key = Some(parse_str::<Type>(s)?),
+                "value" => value = Some(parse_str::<Type>(s)?),
+                _ => Err(each_error(nested))?,
+            },
+            _ => Err(each_error(nested))?,
+        }
+    }
+    let items = match (item, key, value) {
+        (Some(item), None, None) => vec![item],
+        (None, Some(key), Some(value)) => vec![key, value],
+        _ => Err(each_error(ml))?,
+    };
+    match name {
+        Some(name) => Ok(Each { name, items }),
+        None => Err(each_error(ml)),
+    }
+}
+
+fn type_is_string(ty: &Type) -> bool {
+    match ty {
+        Type::Path(path) if path.qself.is_none() => {
+            path.path.segments.last().is_some_and(|seg| {
+                seg.value().ident == "String" && seg.value().arguments.is_empty()
+            })
+        }
+        _ => false,
+    }
+}
+
+// Every error found in the input, so that they are all reported at once
+// rather than one per compile
+#[derive(Default)]
+struct Errors(Vec<syn::Error>);
+
+impl Errors {
+    fn push(&mut self, e: syn::Error) {
+        self.0.push(e);
+    }
+
+    // Record the error from `result`, if any, and carry on
+    fn check(&mut self, result: syn::Result<()>) {
+        if let Err(e) = result {
+            self.push(e);
+        }
+    }
+
+    fn append(&mut self, other: Errors) {
+        self.0.extend(other.0);
+    }
+
+    fn finish(self) -> Result<(), Errors> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+
+    fn to_compile_errors(&self) -> TS {
+        self.0.iter().map(syn::Error::to_compile_error).collect()
+    }
+}
+
+impl From<syn::Error> for Errors {
+    fn from(e: syn::Error) -> Self {
+        Errors(vec![e])
+    }
+}
+
+fn builder_error<T: ToTokens>(att: T) -> syn::Error {
     syn::Error::new_spanned(att, "expected `builder(each = \"...\")`")
-        .to_compile_error()
-        .into()
 }
 
-fn get_builder_name(f: &Field) -> Result<Option<(Ident, &Type)>, TokenStream> {
-    for att in f.attrs.iter() {
-        if att.path.is_ident("builder") {
-            let ml = match att.parse_meta().unwrap() {
-                Meta::List(l) => l,
-                _ => Err(builder_error(att))?,
-            };
-            if ml.nested.len() != 1 {
-                Err(builder_error(att))?;
+// Parse the contents of a string literal, reporting any error on the literal
+// itself since running out of input has no better place to point
+fn parse_str<T: Parse>(s: &LitStr) -> syn::Result<T> {
+    s.parse().map_err(|e| syn::Error::new_spanned(s, e))
+}
+
+fn parse_default(s: &LitStr) -> syn::Result<TS> {
+    parse_str::<Expr>(s).map(|expr| expr.into_token_stream())
+}
+
+fn parse_vis(lit: &Lit) -> syn::Result<Visibility> {
+    let s = match lit {
+        Lit::Str(s) => s,
+        _ => Err(syn::Error::new_spanned(lit, "expected `vis = \"...\"`"))?,
+    };
+    parse_str(s)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Pattern {
+    // Setters take and return `&mut Self`, `build` clones out of the builder
+    Mutable,
+    // Setters take and return `Self` by value, `build` moves out of it
+    Owned,
+    // Setters take `&self` and return an updated copy of the builder
+    Immutable,
+}
+
+// Options from `setter(...)`, which may be given for the whole struct and then
+// overridden per field
+#[derive(Clone, Default)]
+struct SetterOpts {
+    // Setters accept anything which is `Into` the field type
+    into: Option<bool>,
+    // Setters for `String` fields accept anything which is `AsRef<str>`
+    as_ref: Option<bool>,
+    // Setters for `Option<T>` fields take a `T` rather than an `Option<T>`
+    strip_option: Option<bool>,
+    // No setter at all, with `build` always using the default
+    skip: Option<bool>,
+    // Setters are named `<prefix>_<field>`
+    prefix: Option<Ident>,
+    // The name of one field's setter
+    name: Option<Ident>,
+    // The whole-collection setter of an `each` field adds to the items set so
+    // far rather than replacing them
+    append: Option<bool>,
+}
+
+fn setter_error<T: ToTokens>(att: T) -> syn::Error {
+    syn::Error::new_spanned(
+        att,
+        "expected `setter(into)`, `setter(as_ref)`, `setter(strip_option)`, `setter(skip)`, `setter(append)`, `setter(prefix = \"...\")` or `setter(name = \"...\")`",
+    )
+}
+
+fn setter_opts(ml: &MetaList, opts: &mut SetterOpts) -> syn::Result<()> {
+    for nested in ml.nested.iter() {
+        let (name, value) = match nested {
+            NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+                ident,
+                lit: Lit::Str(s),
+                ..
+            })) if ident == "prefix" => {
+                opts.prefix = Some(parse_str(s)?);
+                continue;
+            }
+            NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+                ident,
+                lit: Lit::Str(s),
+                ..
+            })) if ident == "name" => {
+                opts.name = Some(parse_str(s)?);
+                continue;
+            }
+            NestedMeta::Meta(Meta::Word(w)) => (w, true),
+            NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+                ident,
+                lit: Lit::Bool(b),
+                ..
+            })) => (ident, b.value),
+            _ => Err(setter_error(nested))?,
+        };
+        if name == "into" {
+            opts.into = Some(value);
+        } else if name == "as_ref" {
+            opts.as_ref = Some(value);
+        } else if name == "strip_option" {
+            opts.strip_option = Some(value);
+        } else if name == "skip" {
+            opts.skip = Some(value);
+        } else if name == "append" {
+            opts.append = Some(value);
+        } else {
+            Err(setter_error(nested))?;
+        }
+    }
+    Ok(())
+}
+
+// Options from `build_fn(...)`
+struct BuildFnOpts {
+    name: Ident,
+    // Checks the builder once every required field is set, before building
+    validate: Option<syn::Path>,
+    // Error type returned by `build` instead of the generated one
+    error: Option<Type>,
+}
+
+fn build_fn_error<T: ToTokens>(att: T) -> syn::Error {
+    syn::Error::new_spanned(
+        att,
+        "expected `build_fn(name = \"...\")`, `build_fn(validate = \"...\")` or `build_fn(error = \"...\")`",
+    )
+}
+
+fn build_fn_opts(ml: &MetaList, opts: &mut BuildFnOpts) -> syn::Result<()> {
+    for nested in ml.nested.iter() {
+        let (name, s) = match nested {
+            NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+                ident,
+                lit: Lit::Str(s),
+                ..
+            })) => (ident, s),
+            _ => Err(build_fn_error(nested))?,
+        };
+        let parsed = if name == "name" {
+            parse_str(s).map(|name| opts.name = name)
+        } else if name == "validate" {
+            parse_str(s).map(|path| opts.validate = Some(path))
+        } else if name == "error" {
+            parse_str(s).map(|ty| opts.error = Some(ty))
+        } else {
+            Err(build_fn_error(nested))?
+        };
+        parsed?;
+    }
+    Ok(())
+}
+
+struct ContainerOpts {
+    typestate: bool,
+    default: bool,
+    // Visibility of the builder, its error type, setters and build method
+    vis: Visibility,
+    pattern: Pattern,
+    setter: SetterOpts,
+    build_fn: BuildFnOpts,
+    // Traits from `derive(...)` to derive on the builder
+    derives: Vec<Ident>,
+    // Whether every field gets a `try_` setter
+    try_setter: bool,
+    // Whether the builder can be filled from string keys and values
+    from_kv: bool,
+    // Whether the expansion only needs `core`, with `validate` failing with a
+    // `&'static str` rather than a `String`
+    no_std: bool,
+    // Whether the builder's setters and `build` are `const fn`s, with `build`
+    // panicking on a missing field
+    is_const: bool,
+    // Whether the builder implements serde's `Deserialize`
+    derive_deserialize: bool,
+}
+
+fn container_error<T: ToTokens>(att: T) -> syn::Error {
+    syn::Error::new_spanned(att, "unrecognized builder attribute")
+}
+
+fn container_opts(attrs: &[Attribute], vis: &Visibility, errors: &mut Errors) -> ContainerOpts {
+    let mut opts = ContainerOpts {
+        typestate: false,
+        default: false,
+        vis: vis.clone(),
+        pattern: Pattern::Mutable,
+        setter: SetterOpts::default(),
+        build_fn: BuildFnOpts {
+            name: Ident::new("build", Span::call_site()),
+            validate: None,
+            error: None,
+        },
+        derives: Vec::new(),
+        try_setter: false,
+        from_kv: false,
+        no_std: false,
+        is_const: false,
+        derive_deserialize: false,
+    };
+    let mut pattern_meta = None;
+    let mut build_fn_meta = None;
+    let mut from_kv_meta = None;
+    let mut deserialize_meta = None;
+    let mut const_meta = None;
+    let mut default_meta = None;
+    for att in attrs.iter().filter(|att| att.path.is_ident("builder")) {
+        let ml = match att.parse_meta() {
+            Ok(Meta::List(l)) => l,
+            Ok(_) => {
+                errors.push(container_error(att));
+                continue;
+            }
+            Err(e) => {
+                errors.push(e);
+                continue;
+            }
+        };
+        for nested in ml.nested.iter() {
+            let parsed = match nested {
+                NestedMeta::Meta(Meta::Word(w)) if w == "typestate" => {
+                    opts.typestate = true;
+                    Ok(())
+                }
+                NestedMeta::Meta(Meta::Word(w)) if w == "default" => {
+                    opts.default = true;
+                    default_meta = Some(w.clone());
+                    Ok(())
+                }
+                NestedMeta::Meta(Meta::Word(w)) if w == "const" => {
+                    opts.is_const = true;
+                    const_meta = Some(w.clone());
+                    Ok(())
+                }
+                NestedMeta::Meta(Meta::Word(w)) if w == "try_setter" => {
+                    opts.try_setter = true;
+                    Ok(())
+                }
+                NestedMeta::Meta(Meta::Word(w)) if w == "no_std" => {
+                    opts.no_std = true;
+                    Ok(())
+                }
+                NestedMeta::Meta(Meta::Word(w)) if w == "from_kv" => {
+                    opts.from_kv = true;
+                    from_kv_meta = Some(w.clone());
+                    Ok(())
+                }
+                NestedMeta::Meta(Meta::Word(w)) if w == "derive_deserialize" => {
+                    if !cfg!(feature = "serde") {
+                        Err(syn::Error::new_spanned(
+                            w,
+                            "`derive_deserialize` needs the `serde` feature of derive_builder",
+                        ))
+                    } else {
+                        opts.derive_deserialize = true;
+                        deserialize_meta = Some(w.clone());
+                        Ok(())
+                    }
+                }
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.ident == "vis" => {
+                    parse_vis(&nv.lit).map(|vis| opts.vis = vis)
+                }
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.ident == "pattern" => {
+                    let pattern = match &nv.lit {
+                        Lit::Str(s) if s.value() == "mutable" => Ok(Pattern::Mutable),
+                        Lit::Str(s) if s.value() == "owned" => Ok(Pattern::Owned),
+                        Lit::Str(s) if s.value() == "immutable" => Ok(Pattern::Immutable),
+                        _ => Err(syn::Error::new_spanned(
+                            &nv.lit,
+                            "expected `pattern = \"mutable\"`, `\"owned\"` or `\"immutable\"`",
+                        )),
+                    };
+                    pattern.map(|pattern| {
+                        opts.pattern = pattern;
+                        pattern_meta = Some(nested.clone());
+                    })
+                }
+                NestedMeta::Meta(Meta::List(l)) if l.ident == "setter" => {
+                    setter_opts(l, &mut opts.setter).and_then(|()| match opts.setter.name.take() {
+                        Some(name) => Err(syn::Error::new_spanned(
+                            name,
+                            "`setter(name)` names one field's setter, so it goes on the field",
+                        )),
+                        None => Ok(()),
+                    })
+                }
+                NestedMeta::Meta(Meta::List(l)) if l.ident == "derive" => {
+                    l.nested.iter().try_for_each(|nested| match nested {
+                        // Every builder implements `Default` already.
+                        NestedMeta::Meta(Meta::Word(w)) if w == "Default" => Ok(()),
+                        NestedMeta::Meta(Meta::Word(w)) => {
+                            opts.derives.push(w.clone());
+                            Ok(())
+                        }
+                        _ => Err(syn::Error::new_spanned(
+                            nested,
+                            "expected `derive(Trait, ...)`",
+                        )),
+                    })
+                }
+                NestedMeta::Meta(Meta::List(l)) if l.ident == "build_fn" => {
+                    build_fn_meta = Some(nested.clone());
+                    build_fn_opts(l, &mut opts.build_fn)
+                }
+                _ => Err(container_error(nested)),
+            };
+            errors.check(parsed);
+        }
+    }
+    if let Some(meta) = &pattern_meta {
+        if opts.typestate && opts.pattern != Pattern::Owned {
+            errors.push(syn::Error::new_spanned(
+                meta,
+                "typestate builders always use the owned pattern",
+            ));
+        }
+    }
+    // A const builder is always owned, since a `const fn` can't take `&mut
+    // self`, and it can't call `Default` or any other trait method.
+    if let Some(meta) = &const_meta {
+        let conflict = if opts.typestate {
+            Some("typestate")
+        } else if opts.pattern != Pattern::Owned && pattern_meta.is_some() {
+            Some("pattern")
+        } else if opts.build_fn.validate.is_some() || opts.build_fn.error.is_some() {
+            Some("build_fn")
+        } else if default_meta.is_some() {
+            Some("default")
+        } else if from_kv_meta.is_some() {
+            Some("from_kv")
+        } else {
+            None
+        };
+        if let Some(conflict) = conflict {
+            errors.push(syn::Error::new_spanned(
+                meta,
+                format!("`const` cannot be combined with `{}`", conflict),
+            ));
+        }
+        opts.pattern = Pattern::Owned;
+    }
+    if let Some(meta) = from_kv_meta {
+        if opts.typestate {
+            errors.push(syn::Error::new_spanned(
+                &meta,
+                "`from_kv` can leave required fields unset, so it needs a builder whose build can fail rather than `typestate`",
+            ));
+        }
+        if opts.no_std {
+            errors.push(syn::Error::new_spanned(
+                meta,
+                "`from_kv` reads the environment and reports errors with `String`s, so it needs `std`",
+            ));
+        }
+    }
+    if let Some(meta) = deserialize_meta {
+        if opts.typestate {
+            errors.push(syn::Error::new_spanned(
+                meta,
+                "`derive_deserialize` can leave required fields unset, so it needs a builder whose build can fail rather than `typestate`",
+            ));
+        }
+    }
+    if let Some(meta) = build_fn_meta {
+        if opts.typestate && (opts.build_fn.validate.is_some() || opts.build_fn.error.is_some()) {
+            errors.push(syn::Error::new_spanned(
+                meta,
+                "typestate builders cannot fail, so they take no `validate` or `error`",
+            ));
+        }
+    }
+    opts
+}
+
+// A `#[builder(each = "name")]` setter adding single items to a collection
+struct Each {
+    name: Ident,
+    // The item type, or the key and value types of a map
+    items: Vec<Type>,
+}
+
+impl Each {
+    // The setter's arguments, which are named after the setter itself unless
+    // it takes a key and a value
+    fn args(&self) -> Vec<(Ident, &Type)> {
+        match &self.items[..] {
+            [key, value] => vec![
+                (Ident::new("key", Span::call_site()), key),
+                (Ident::new("value", Span::call_site()), value),
+            ],
+            _ => vec![(self.name.clone(), &self.items[0])],
+        }
+    }
+
+    // The collection's `Extend` item given the setter's argument values
+    fn item(&self, values: &[TS]) -> TS {
+        match values {
+            [value] => value.clone(),
+            _ => quote!((#(#values),*)),
+        }
+    }
+
+    fn item_type(&self) -> TS {
+        match &self.items[..] {
+            [item] => quote!(#item),
+            items => quote!((#(#items),*)),
+        }
+    }
+}
+
+struct BuilderField<'a> {
+    // The name of the field on the builder, which for a tuple struct field is
+    // `_0`, `_1`, ... unless given a `name`
+    id: Ident,
+    setter_name: Ident,
+    // The field's doc comments, which its setter shares
+    docs: Vec<&'a Attribute>,
+    // The field of the built struct
+    member: Member,
+    ty: &'a Type,
+    each: Option<Each>,
+    default: Option<TS>,
+    container_default: bool,
+    setter_vis: Visibility,
+    // Whether `merge` adds the other builder's items to an `each` collection
+    // rather than replacing it
+    merge_append: bool,
+    into: bool,
+    as_ref: bool,
+    strip_option: bool,
+    // The field has no setter and is always built from its default
+    skip: bool,
+    // Whether the whole-collection setter of an `each` field appends
+    append: bool,
+    sub_builder: Option<SubBuilder>,
+    // Whether the field also gets a `try_` setter converting with `TryInto`
+    try_setter: bool,
+    // The key naming the field for `set_from_str`, `from_env` and
+    // deserializing
+    key: String,
+}
+
+// The builder and error type of a field built with its own builder, found by
+// appending `Builder` and `BuilderError` to the name of the field's type.  The
+// inner struct's `pattern` and `build_fn` can't be seen from here, so a field
+// whose inner builder differs from the defaults has to repeat them in
+// `sub_builder(...)`.
+struct SubBuilder {
+    builder: Type,
+    error: Type,
+    build_name: Ident,
+    // Whether the inner `build` takes the builder by value
+    owned: bool,
+}
+
+fn sub_builder(ty: &Type) -> syn::Result<SubBuilder> {
+    let path = match ty {
+        Type::Path(tp) if tp.qself.is_none() && !field_is_optional(ty) => &tp.path,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                ty,
+                "`sub_builder` needs a struct type which derives `Builder`",
+            ))
+        }
+    };
+    let renamed = |suffix: &str, keep_args: bool| {
+        let mut path = path.clone();
+        let last = &mut path.segments.last_mut().unwrap().into_value();
+        last.ident = Ident::new(&format!("{}{}", last.ident, suffix), last.ident.span());
+        if !keep_args {
+            last.arguments = PathArguments::None;
+        }
+        Type::Path(syn::TypePath { qself: None, path })
+    };
+    Ok(SubBuilder {
+        builder: renamed("Builder", true),
+        error: renamed("BuilderError", false),
+        build_name: Ident::new("build", Span::call_site()),
+        owned: false,
+    })
+}
+
+fn sub_typestate_error<T: ToTokens>(att: T) -> syn::Error {
+    syn::Error::new_spanned(
+        att,
+        "`sub_builder` cannot be used with `typestate` builders, which can't fail to build",
+    )
+}
+
+fn sub_builder_error<T: ToTokens>(att: T) -> syn::Error {
+    syn::Error::new_spanned(
+        att,
+        "expected `sub_builder(pattern = \"...\")` or `sub_builder(build_fn(...))` matching the inner struct's builder",
+    )
+}
+
+// Options from `sub_builder(...)`, which repeat those of the inner struct
+fn sub_builder_opts(ml: &MetaList, from_kv: bool, sub: &mut SubBuilder) -> syn::Result<()> {
+    for nested in ml.nested.iter() {
+        match nested {
+            NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+                ident,
+                lit: Lit::Str(s),
+                ..
+            })) if ident == "pattern" => match s.value().as_str() {
+                "owned" => sub.owned = true,
+                "mutable" | "immutable" => sub.owned = false,
+                _ => Err(syn::Error::new_spanned(
+                    s,
+                    "expected `pattern = \"mutable\"`, `\"owned\"` or `\"immutable\"`",
+                ))?,
+            },
+            NestedMeta::Meta(Meta::List(l)) if l.ident == "build_fn" => {
+                let mut build_fn = BuildFnOpts {
+                    name: sub.build_name.clone(),
+                    validate: None,
+                    error: None,
+                };
+                build_fn_opts(l, &mut build_fn)?;
+                if build_fn.validate.is_some() {
+                    Err(syn::Error::new_spanned(
+                        l,
+                        "`validate` only matters to the inner struct's own builder",
+                    ))?;
+                }
+                sub.build_name = build_fn.name;
+                if let Some(error) = build_fn.error {
+                    // `set_from_str` hands on the inner builder's generated
+                    // error, so that's the one the variant has to hold.
+                    if from_kv {
+                        Err(syn::Error::new_spanned(
+                            l,
+                            "`from_kv` needs the inner builder's generated error, so `build_fn(error)` cannot be used",
+                        ))?;
+                    }
+                    sub.error = error;
+                }
+            }
+            _ => Err(sub_builder_error(nested))?,
+        }
+    }
+    Ok(())
+}
+
+impl BuilderField<'_> {
+    fn is_optional(&self) -> bool {
+        field_is_optional(self.ty)
+    }
+
+    // Whether the builder holds this `Option` field as it is, with its setter
+    // taking the inner value, rather than wrapping it in another `Option`
+    fn strips_option(&self) -> bool {
+        self.is_optional() && self.strip_option
+    }
+
+    // The type of value the setter for this field stores
+    fn setter_type(&self) -> &Type {
+        if self.strips_option() {
+            optional_type(self.ty)
+        } else {
+            self.ty
+        }
+    }
+
+    // The type the builder holds in its `Option` for this field
+    fn stored_type(&self) -> &Type {
+        match &self.sub_builder {
+            Some(sub) => &sub.builder,
+            None => self.setter_type(),
+        }
+    }
+
+    fn uses_container_default(&self) -> bool {
+        self.container_default
+            && self.default.is_none()
+            && self.each.is_none()
+            && self.sub_builder.is_none()
+    }
+
+    // The value to use for this field if it was never set on the builder
+    fn default_value(&self) -> Option<TS> {
+        let member = &self.member;
+        if self.uses_container_default() {
+            Some(quote!(__default.#member))
+        } else {
+            self.default.clone()
+        }
+    }
+
+    fn is_required(&self) -> bool {
+        !self.is_optional()
+            && self.each.is_none()
+            && self.sub_builder.is_none()
+            && self.default_value().is_none()
+    }
+}
+
+fn builder_field<'a>(
+    f: &'a Field,
+    index: usize,
+    opts: &ContainerOpts,
+    errors: &mut Errors,
+) -> BuilderField<'a> {
+    let mut name = None;
+    let mut each = None;
+    let mut merge = None;
+    let mut default = None;
+    let mut sub = None;
+    let mut try_setter = None;
+    let mut key = None;
+    let mut setter_vis = opts.vis.clone();
+    let mut setter = SetterOpts::default();
+    for att in f.attrs.iter().filter(|att| att.path.is_ident("builder")) {
+        let ml = match att.parse_meta() {
+            Ok(Meta::List(l)) => l,
+            Ok(_) => {
+                errors.push(builder_error(att));
+                continue;
+            }
+            Err(e) => {
+                errors.push(e);
+                continue;
+            }
+        };
+        for nested in ml.nested.iter() {
+            let parsed = match nested {
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.ident == "each" => {
+                    match (&nv.lit, collection_items(&f.ty)) {
+                        (Lit::Str(s), Some(items)) => {
+                            parse_str(s).map(|name| each = Some(Each { name, items }))
+                        }
+                        (Lit::Str(_), None) => Err(syn::Error::new_spanned(
+                            &f.ty,
+                            "`each` takes the item type from the collection's first type argument, or the key and value types from the first two of a `...Map`, so without them it needs `each(name = \"...\", item = \"...\")`",
+                        )),
+                        _ => Err(builder_error(&ml)),
+                    }
+                }
+                NestedMeta::Meta(Meta::List(l)) if l.ident == "each" => {
+                    each_opts(l).map(|found| each = Some(found))
+                }
+                NestedMeta::Meta(Meta::Word(w)) if w == "default" => {
+                    default = Some((nested.clone(), quote!(::core::default::Default::default())));
+                    Ok(())
+                }
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.ident == "default" => match &nv.lit {
+                    Lit::Str(s) => {
+                        parse_default(s).map(|expr| default = Some((nested.clone(), expr)))
+                    }
+                    _ => Err(builder_error(&ml)),
+                },
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.ident == "vis" => {
+                    parse_vis(&nv.lit).map(|vis| setter_vis = vis)
+                }
+                NestedMeta::Meta(Meta::Word(w)) if w == "skip" => {
+                    setter.skip = Some(true);
+                    Ok(())
+                }
+                NestedMeta::Meta(Meta::Word(w)) if w == "sub_builder" => {
+                    if opts.typestate {
+                        Err(sub_typestate_error(w))
+                    } else {
+                        sub_builder(&f.ty).map(|found| sub = Some((w.clone(), found)))
+                    }
+                }
+                NestedMeta::Meta(Meta::List(l)) if l.ident == "sub_builder" => {
+                    if opts.typestate {
+                        Err(sub_typestate_error(&l.ident))
+                    } else {
+                        sub_builder(&f.ty).and_then(|mut found| {
+                            sub_builder_opts(l, opts.from_kv, &mut found)?;
+                            sub = Some((l.ident.clone(), found));
+                            Ok(())
+                        })
+                    }
+                }
+                NestedMeta::Meta(Meta::Word(w)) if w == "try_setter" => {
+                    try_setter = Some(w.clone());
+                    Ok(())
+                }
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.ident == "key" => match &nv.lit {
+                    Lit::Str(s) if !opts.from_kv && !opts.derive_deserialize => {
+                        Err(syn::Error::new_spanned(
+                            s,
+                            "`key` is only used by a builder with `from_kv` or `derive_deserialize`",
+                        ))
+                    }
+                    Lit::Str(s) => {
+                        key = Some(s.value());
+                        Ok(())
+                    }
+                    _ => Err(builder_error(&ml)),
+                },
+                NestedMeta::Meta(Meta::Word(w)) if w == "private" => {
+                    setter_vis = Visibility::Inherited;
+                    Ok(())
+                }
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.ident == "merge" => match &nv.lit {
+                    Lit::Str(s) if s.value() == "append" => {
+                        merge = Some((nested.clone(), true));
+                        Ok(())
+                    }
+                    Lit::Str(s) if s.value() == "replace" => {
+                        merge = Some((nested.clone(), false));
+                        Ok(())
+                    }
+                    _ => Err(syn::Error::new_spanned(
+                        &nv.lit,
+                        "expected `merge = \"append\"` or `merge = \"replace\"`",
+                    )),
+                },
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.ident == "name" => {
+                    match (&nv.lit, &f.ident) {
+                        (Lit::Str(s), None) => parse_str(s).map(|ident| name = Some(ident)),
+                        (Lit::Str(_), Some(_)) => Err(syn::Error::new_spanned(
+                            nested,
+                            "`name` is only needed on tuple struct fields",
+                        )),
+                        _ => Err(builder_error(&ml)),
+                    }
+                }
+                NestedMeta::Meta(Meta::List(l)) if l.ident == "setter" => {
+                    setter_opts(l, &mut setter)
+                }
+                _ => Err(builder_error(&ml)),
+            };
+            errors.check(parsed);
+        }
+    }
+    if let (Some(_), Some((nested, _))) = (&each, &default) {
+        errors.push(syn::Error::new_spanned(
+            nested,
+            "`default` cannot be combined with `each`",
+        ));
+        default = None;
+    }
+    if let (None, Some((nested, true))) = (&each, &merge) {
+        errors.push(syn::Error::new_spanned(
+            nested,
+            "only `each` collections can be merged by appending",
+        ));
+    }
+    if let (None, Some(true)) = (&each, setter.append) {
+        errors.push(syn::Error::new_spanned(
+            &f.ty,
+            "only `each` collections can be set by appending",
+        ));
+    }
+    if let (Some((w, _)), Some(_)) = (&sub, &try_setter) {
+        errors.push(syn::Error::new_spanned(
+            w,
+            "`sub_builder` cannot be combined with `try_setter`",
+        ));
+    }
+    if opts.is_const {
+        // A default value is fine, but not one from `Default`, which is what a
+        // bare `default` or a `skip` with no value of its own fall back to.
+        let conflict = if each.is_some() {
+            Some("each")
+        } else if let Some((NestedMeta::Meta(Meta::Word(_)), _)) = default {
+            Some("default")
+        } else if default.is_none() && setter.skip.or(opts.setter.skip) == Some(true) {
+            Some("skip")
+        } else if sub.is_some() {
+            Some("sub_builder")
+        } else if try_setter.is_some() || opts.try_setter {
+            Some("try_setter")
+        } else if setter.into.or(opts.setter.into) == Some(true) {
+            Some("setter(into)")
+        } else if setter.as_ref.or(opts.setter.as_ref) == Some(true) {
+            Some("setter(as_ref)")
+        } else {
+            None
+        };
+        if let Some(conflict) = conflict {
+            errors.push(syn::Error::new_spanned(
+                &f.ty,
+                format!(
+                    "`{}` needs trait methods, which a `const` builder can't call",
+                    conflict
+                ),
+            ));
+        }
+    }
+    if let Some((w, _)) = &sub {
+        let conflict = if each.is_some() {
+            Some("each")
+        } else if default.is_some() {
+            Some("default")
+        } else if setter.skip.or(opts.setter.skip) == Some(true) {
+            Some("skip")
+        } else {
+            None
+        };
+        if let Some(conflict) = conflict {
+            errors.push(syn::Error::new_spanned(
+                w,
+                format!("`sub_builder` cannot be combined with `{}`", conflict),
+            ));
+        }
+    }
+    let mut default = default.map(|(_, default)| default);
+    // A skipped field is built from its default, or failing that `Default`
+    let skip = setter.skip.or(opts.setter.skip).unwrap_or(false);
+    if skip {
+        if let Some(each) = &each {
+            errors.push(syn::Error::new_spanned(
+                &each.name,
+                "`each` cannot be combined with `skip`",
+            ));
+        }
+        if default.is_none() && !opts.default {
+            default = Some(quote!(::core::default::Default::default()));
+        }
+    }
+    let into = setter.into.or(opts.setter.into).unwrap_or(false);
+    let as_ref = setter.as_ref.or(opts.setter.as_ref).unwrap_or(false);
+    if into && as_ref {
+        errors.push(syn::Error::new_spanned(
+            &f.ty,
+            "`setter(into)` cannot be combined with `setter(as_ref)`",
+        ));
+    }
+    let (id, member) = match &f.ident {
+        Some(ident) => (ident.clone(), Member::Named(ident.clone())),
+        None => (
+            name.unwrap_or_else(|| Ident::new(&format!("_{}", index), Span::call_site())),
+            Member::Unnamed(Index {
+                index: index as u32,
+                span: Span::call_site(),
+            }),
+        ),
+    };
+    let setter_name = match (setter.name, setter.prefix.or(opts.setter.prefix.clone())) {
+        (Some(name), _) => name,
+        (None, Some(prefix)) => Ident::new(
+            &format!("{}_{}", prefix, id.to_string().trim_start_matches("r#")),
+            id.span(),
+        ),
+        (None, None) => id.clone(),
+    };
+    // A builder deserialized in place of the struct reads the same keys, so a
+    // field serde renames keeps its serde name unless given a key of its own.
+    let key = key
+        .or_else(|| match opts.derive_deserialize {
+            true => serde_rename(&f.attrs),
+            false => None,
+        })
+        .unwrap_or_else(|| id.to_string().trim_start_matches("r#").to_owned());
+    let field = BuilderField {
+        id,
+        setter_name,
+        key,
+        docs: f
+            .attrs
+            .iter()
+            .filter(|att| att.path.is_ident("doc"))
+            .collect(),
+        member,
+        ty: &f.ty,
+        each,
+        default,
+        container_default: opts.default,
+        setter_vis,
+        merge_append: merge.is_none_or(|(_, append)| append),
+        into,
+        as_ref,
+        strip_option: setter
+            .strip_option
+            .or(opts.setter.strip_option)
+            .unwrap_or(true),
+        skip,
+        append: setter.append.or(opts.setter.append).unwrap_or(false),
+        try_setter: try_setter.is_some() || (opts.try_setter && sub.is_none()),
+        sub_builder: sub.map(|(_, sub)| sub),
+    };
+    // A struct wide `setter(as_ref)` only affects `String` fields, but asking
+    // for it on any other field is a mistake.
+    let takes_string = type_is_string(field.setter_type())
+        || field
+            .each
+            .as_ref()
+            .is_some_and(|each| each.items.iter().any(type_is_string));
+    if setter.as_ref == Some(true) && !takes_string {
+        errors.push(syn::Error::new_spanned(
+            &f.ty,
+            "`setter(as_ref)` is only supported on `String` fields",
+        ));
+    }
+    field
+}
+
+fn camel_case(name: &str) -> String {
+    let name = name.trim_start_matches("r#");
+    let mut ret = String::new();
+    let mut upper = true;
+    for ch in name.chars() {
+        if ch == '_' {
+            upper = true;
+        } else if upper {
+            ret.extend(ch.to_uppercase());
+            upper = false;
+        } else {
+            ret.push(ch);
+        }
+    }
+    ret
+}
+
+fn snake_case(name: &str) -> String {
+    let chars: Vec<_> = name.chars().collect();
+    let mut ret = String::new();
+    for (i, ch) in chars.iter().enumerate() {
+        if ch.is_uppercase() && i > 0 {
+            let prev = chars[i - 1];
+            let next_lower = chars.get(i + 1).is_some_and(|c| c.is_lowercase());
+            if !prev.is_uppercase() || next_lower {
+                ret.push('_');
+            }
+        }
+        ret.extend(ch.to_lowercase());
+    }
+    ret
+}
+
+fn missing_variant(id: &Ident) -> Ident {
+    Ident::new(
+        &format!("Missing{}", camel_case(&id.to_string())),
+        id.span(),
+    )
+}
+
+// The error variant wrapping a sub-builder's error, named after its field
+fn invalid_variant(id: &Ident) -> Ident {
+    Ident::new(
+        &format!("Invalid{}", camel_case(&id.to_string())),
+        id.span(),
+    )
+}
+
+// Reports fields whose error variant is already taken, either by one of the
+// fixed variants or by another field whose name differs only in underscores.
+fn check_error_variants(fields: &[BuilderField], opts: &ContainerOpts, errors: &mut Errors) {
+    let mut taken = vec!["Validation".to_owned()];
+    if opts.from_kv {
+        taken.push("UnknownKey".to_owned());
+        taken.push("InvalidValue".to_owned());
+    }
+    for f in fields {
+        let variant = match &f.sub_builder {
+            Some(_) => invalid_variant(&f.id),
+            None if f.is_required() => missing_variant(&f.id),
+            None => continue,
+        };
+        let variant = variant.to_string();
+        if taken.contains(&variant) {
+            errors.push(field_error(
+                f,
+                format!(
+                    "this field's error variant `{}` is already taken, so the field needs another name",
+                    variant
+                ),
+            ));
+        } else {
+            taken.push(variant);
+        }
+    }
+}
+
+// Reports fields with a method whose name is already taken, either by one of
+// the builder's own methods, like `merge` for a field named `merge`, or by
+// another field's, like `get_port` for fields `port` and `get_port`.
+fn check_method_names(fields: &[BuilderField], opts: &ContainerOpts, errors: &mut Errors) {
+    let mut taken = vec![opts.build_fn.name.to_string()];
+    if !opts.typestate {
+        taken.push("merge".to_owned());
+        taken.push("or".to_owned());
+    }
+    if opts.from_kv {
+        taken.push("set_from_str".to_owned());
+        taken.push("from_env".to_owned());
+    }
+    for f in fields {
+        let mut names = vec![
+            f.setter_name.clone(),
+            prefixed("get_", &f.id),
+            prefixed("is_set_", &f.id),
+        ];
+        // A typestate builder can't take a required field back to `Unset`.
+        if !opts.typestate || !f.is_required() {
+            names.push(prefixed("clear_", &f.id));
+        }
+        if let Some(each) = &f.each {
+            names.push(each.name.clone());
+            names.push(prefixed("extend_", &f.id));
+        }
+        if f.try_setter {
+            names.push(prefixed("try_", &f.setter_name));
+        }
+        let mut names: Vec<_> = names.iter().map(ToString::to_string).collect();
+        names.sort();
+        names.dedup();
+        for name in names {
+            if taken.contains(&name) {
+                errors.push(field_error(
+                    f,
+                    format!(
+                        "this field's method `{}` is already taken by another method of the builder, so the field needs another name",
+                        name
+                    ),
+                ));
+            } else {
+                taken.push(name);
+            }
+        }
+    }
+}
+
+// An error pointing at a field's name, or its type for a tuple struct field
+fn field_error(f: &BuilderField, message: String) -> syn::Error {
+    match &f.member {
+        Member::Named(id) => syn::Error::new_spanned(id, message),
+        Member::Unnamed(_) => syn::Error::new_spanned(f.ty, message),
+    }
+}
+
+// Generates setters for the chosen builder pattern.  Setter bodies update the
+// builder through `receiver()`, which for the immutable pattern is a fresh copy
+// of `self` built by `clone_self`.
+struct Setters {
+    pattern: Pattern,
+    clone_self: TS,
+    clone_where: TS,
+    // `const` for a const builder
+    constness: TS,
+}
+
+impl Setters {
+    fn receiver(&self) -> TS {
+        match self.pattern {
+            Pattern::Immutable => quote!(__builder),
+            _ => quote!(self),
+        }
+    }
+
+    // The setter for field `f` taking a `ty`, after any conversion, and
+    // storing it with the statements from `store`.
+    fn setter(&self, f: &BuilderField, ty: &Type, store: &dyn Fn(&TS) -> TS) -> TS {
+        let docs = &f.docs;
+        let setter = self.setter_args(f, &f.setter_name, &[(f.id.clone(), ty)], &|values| {
+            store(&values[0])
+        });
+        quote! {
+            #(#docs)*
+            #setter
+        }
+    }
+
+    // Like `setter`, but taking several arguments, each converted on its own.
+    fn setter_args(
+        &self,
+        f: &BuilderField,
+        name: &Ident,
+        args: &[(Ident, &Type)],
+        store: &dyn Fn(&[TS]) -> TS,
+    ) -> TS {
+        let mut bounds = Vec::new();
+        let mut params = Vec::new();
+        let mut values = Vec::new();
+        for (i, (arg, ty)) in args.iter().enumerate() {
+            let param = if args.len() == 1 {
+                Ident::new("__V", Span::call_site())
+            } else {
+                Ident::new(&format!("__V{}", i), Span::call_site())
+            };
+            let (bound, arg_ty, value) = setter_arg(f, arg, ty, &param);
+            bounds.extend(bound);
+            params.push(quote!(#arg: #arg_ty));
+            values.push(value);
+        }
+        let generics = if bounds.is_empty() {
+            quote! {}
+        } else {
+            quote!(<#(#bounds),*>)
+        };
+        self.method(
+            &f.setter_vis,
+            name,
+            generics,
+            quote!(#(#params),*),
+            store(&values),
+        )
+    }
+
+    // `try_<field>`, which converts its argument to a `ty` with `TryInto` and
+    // hands back the conversion error rather than the builder if that fails.
+    fn try_setter(&self, f: &BuilderField, ty: &Type, store: &dyn Fn(&TS) -> TS) -> TS {
+        let id = &f.id;
+        let docs = &f.docs;
+        let store = store(&quote!(#id));
+        let setter = self.fallible_method(
+            &f.setter_vis,
+            &prefixed("try_", &f.setter_name),
+            quote!(<__V: ::core::convert::TryInto<#ty>>),
+            quote!(#id: __V),
+            quote! {
+                let #id = ::core::convert::TryInto::try_into(#id)?;
+                #store
+            },
+            Some(quote!(<__V as ::core::convert::TryInto<#ty>>::Error)),
+        );
+        quote! {
+            #(#docs)*
+            #setter
+        }
+    }
+
+    // A method in the builder's pattern with the given generics, parameters
+    // and body updating the builder.
+    fn method(&self, vis: &Visibility, name: &Ident, generics: TS, params: TS, body: TS) -> TS {
+        self.fallible_method(vis, name, generics, params, body, None)
+    }
+
+    // Like `method`, but returning a `Result` with the given error type if
+    // there is one, so the body can use `?`.
+    fn fallible_method(
+        &self,
+        vis: &Visibility,
+        name: &Ident,
+        generics: TS,
+        params: TS,
+        body: TS,
+        error: Option<TS>,
+    ) -> TS {
+        let (receiver, ret, clone_self, out) = match self.pattern {
+            Pattern::Mutable => (
+                quote!(&mut self),
+                quote!(&mut Self),
+                quote! {},
+                quote!(self),
+            ),
+            Pattern::Owned => (quote!(mut self), quote!(Self), quote! {}, quote!(self)),
+            Pattern::Immutable => {
+                let clone_self = &self.clone_self;
+                (
+                    quote!(&self),
+                    quote!(Self),
+                    quote!(let mut __builder = #clone_self;),
+                    quote!(__builder),
+                )
+            }
+        };
+        let (ret, out) = match error {
+            Some(error) => (
+                quote!(::core::result::Result<#ret, #error>),
+                quote!(::core::result::Result::Ok(#out)),
+            ),
+            None => (ret, out),
+        };
+        let clone_where = match self.pattern {
+            Pattern::Immutable => self.clone_where.clone(),
+            _ => quote! {},
+        };
+        let constness = &self.constness;
+        quote! {
+            #vis #constness fn #name #generics(#receiver, #params) -> #ret #clone_where {
+                #clone_self
+                #body
+                #out
+            }
+        }
+    }
+}
+
+// The bound on generic parameter `param`, if the setter needs one, and the type
+// of a setter's argument `arg` for a value of type `ty`, along with the
+// expression converting the argument to a `ty`.
+fn setter_arg(f: &BuilderField, arg: &Ident, ty: &Type, param: &Ident) -> (Option<TS>, TS, TS) {
+    if f.into {
+        (
+            Some(quote!(#param: ::core::convert::Into<#ty>)),
+            quote!(#param),
+            quote!(::core::convert::Into::into(#arg)),
+        )
+    } else if f.as_ref && type_is_string(ty) {
+        (
+            Some(quote!(#param: ::core::convert::AsRef<str>)),
+            quote!(#param),
+            quote!(<#ty as ::core::convert::From<&str>>::from(
+                ::core::convert::AsRef::<str>::as_ref(&#arg)
+            )),
+        )
+    } else {
+        (None, quote!(#ty), quote!(#arg))
+    }
+}
+
+fn derive_attr(opts: &ContainerOpts) -> TS {
+    let derives = &opts.derives;
+    if derives.is_empty() {
+        quote! {}
+    } else {
+        quote!(#[derive(#(#derives),*)])
+    }
+}
+
+// `id` with a prefix, like `extend_args` for `args`
+fn prefixed(prefix: &str, id: &Ident) -> Ident {
+    let name = id.to_string();
+    Ident::new(
+        &format!("{}{}", prefix, name.trim_start_matches("r#")),
+        id.span(),
+    )
+}
+
+// `get_<field>` and `is_set_<field>` for looking at what has been set so far.
+fn getters(fields: &[BuilderField]) -> Vec<TS> {
+    fields
+        .iter()
+        .map(|f| {
+            let id = &f.id;
+            let vis = &f.setter_vis;
+            let ty = f.stored_type();
+            let get = prefixed("get_", id);
+            let is_set = prefixed("is_set_", id);
+            quote! {
+                #vis fn #get(&self) -> ::core::option::Option<&#ty> {
+                    self.#id.as_ref()
+                }
+
+                #vis fn #is_set(&self) -> bool {
+                    self.#id.is_some()
+                }
+            }
+        })
+        .collect()
+}
+
+// The `each` setter adding one item to the collection, and `extend_<field>`
+// adding any number of them.  `collection` is a `&mut` to the collection.
+fn each_setters(setters: &Setters, f: &BuilderField, each: &Each, collection: &TS) -> TS {
+    let item_setter = setters.setter_args(f, &each.name, &each.args(), &|values| {
+        let item = each.item(values);
+        quote! {
+            ::core::iter::Extend::extend(#collection, ::core::iter::once(#item));
+        }
+    });
+    let extend = prefixed("extend_", &f.id);
+    let item_type = each.item_type();
+    let extend_setter = setters.method(
+        &f.setter_vis,
+        &extend,
+        quote!(<__I: ::core::iter::IntoIterator<Item = #item_type>>),
+        quote!(items: __I),
+        quote! {
+            ::core::iter::Extend::extend(#collection, items);
+        },
+    );
+    quote! {
+        #item_setter
+        #extend_setter
+    }
+}
+
+// Produce the expression for a field of the built struct given `value`, the
+// builder's `Option` for that field.  Unset fields fall back to their default
+// if they have one, otherwise to `missing` for required fields.
+fn build_value(f: &BuilderField, value: TS, missing: TS) -> TS {
+    match (f.strips_option(), f.default_value()) {
+        (true, None) => value,
+        (true, Some(default)) => quote! {
+            match #value {
+                ::core::option::Option::Some(v) => ::core::option::Option::Some(v),
+                ::core::option::Option::None => #default,
+            }
+        },
+        (false, default) => {
+            let fallback = match default {
+                Some(default) => default,
+                None if f.is_optional() => quote!(::core::option::Option::None),
+                None => missing,
+            };
+            quote! {
+                match #value {
+                    ::core::option::Option::Some(v) => v,
+                    ::core::option::Option::None => #fallback,
+                }
+            }
+        }
+    }
+}
+
+// Statements binding each field of the built struct to a local named after
+// it, either cloned out of the builder or, with the owned pattern, moved out
+// of it, along with the initializers of the struct expression.  Fields with a
+// default come after those without, and skipped fields last, so a default
+// expression can use the fields bound before it.  A sub-builder's error is
+// wrapped with the constructor from `invalid`.
+fn build_fields(
+    fields: &[&BuilderField],
+    pattern: Pattern,
+    missing: &dyn Fn(&Ident) -> TS,
+    invalid: &dyn Fn(&Ident) -> TS,
+) -> (Vec<TS>, Vec<TS>) {
+    let owned = pattern == Pattern::Owned;
+    let mut ordered = fields.to_vec();
+    ordered.sort_by_key(|f| (f.skip, f.default_value().is_some()));
+    let lets = ordered
+        .iter()
+        .map(|f| {
+            let id = &f.id;
+            let value = match (&f.each, &f.sub_builder, owned) {
+                _ if f.skip => f.default_value().unwrap(),
+                // An unset sub-builder builds from its defaults, which is only
+                // an error if the inner struct has required fields.  An inner
+                // builder whose `build` takes it by value is cloned out of a
+                // `&mut` one, and an unset one is built from a temporary so
+                // the field stays unset.
+                (_, Some(sub), _) => {
+                    let build_name = &sub.build_name;
+                    let inner = &sub.builder;
+                    let built = match pattern {
+                        Pattern::Mutable if sub.owned => quote! {
+                            self.#id
+                                .as_ref()
+                                .map_or_else(::core::default::Default::default, ::core::clone::Clone::clone)
+                                .#build_name()
+                        },
+                        Pattern::Mutable => quote! {
+                            match &mut self.#id {
+                                ::core::option::Option::Some(builder) => builder.#build_name(),
+                                ::core::option::Option::None => {
+                                    <#inner as ::core::default::Default>::default().#build_name()
+                                }
+                            }
+                        },
+                        Pattern::Owned => quote!(self.#id.unwrap_or_default().#build_name()),
+                        Pattern::Immutable => quote! {
+                            ::core::clone::Clone::clone(&self.#id)
+                                .unwrap_or_default()
+                                .#build_name()
+                        },
+                    };
+                    let wrap = invalid(id);
+                    quote!(#built.map_err(#wrap)?)
+                }
+                (Some(_), None, false) => quote! {
+                    self.#id
+                        .as_ref()
+                        .map_or_else(::core::default::Default::default, ::core::clone::Clone::clone)
+                },
+                (Some(_), None, true) => {
+                    quote!(self.#id.unwrap_or_else(::core::default::Default::default))
+                }
+                (None, None, false) => {
+                    build_value(f, quote!(self.#id.as_ref().map(|f| f.clone())), missing(id))
+                }
+                (None, None, true) => build_value(f, quote!(self.#id), missing(id)),
+            };
+            quote! {
+                let #id = #value;
+            }
+        })
+        .collect();
+    let inits = fields
+        .iter()
+        .map(|f| {
+            let id = &f.id;
+            match &f.member {
+                Member::Named(_) => quote!(#id),
+                member => quote!(#member: #id),
+            }
+        })
+        .collect();
+    (lets, inits)
+}
+
+// With a container level `#[builder(default)]` the struct's own `Default` impl
+// supplies every unset field, so `build` needs an instance to take them from.
+fn container_default(struct_ty: &TS, fields: &[&BuilderField]) -> TS {
+    if fields.iter().any(|f| f.uses_container_default()) {
+        quote! {
+            let __default = <#struct_ty as ::core::default::Default>::default();
+        }
+    } else {
+        quote! {}
+    }
+}
+
+// The generic arguments needed to name a type declared with `generics`, i.e.
+// `'a, T, N` for `<'a, T: Trait, const N: usize>`, so more can be appended.
+fn generic_args(generics: &Generics) -> Vec<TS> {
+    generics
+        .params
+        .iter()
+        .map(|param| match param {
+            GenericParam::Type(tp) => {
+                let ident = &tp.ident;
+                quote!(#ident)
+            }
+            GenericParam::Lifetime(lp) => {
+                let lifetime = &lp.lifetime;
+                quote!(#lifetime)
+            }
+            GenericParam::Const(cp) => {
+                let ident = &cp.ident;
+                quote!(#ident)
+            }
+        })
+        .collect()
+}
+
+#[proc_macro_derive(Builder, attributes(builder))]
+pub fn derive(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match derive_builder(input) {
+        Ok(s) => s.into(),
+        Err(errors) => errors.to_compile_errors().into(),
+    }
+}
+
+// What a builder builds: a struct, one variant of an enum, or the result of
+// calling a function
+struct Target<'a> {
+    // The type `build` returns
+    ty: TS,
+    kind: TargetKind,
+    builder_name: Ident,
+    error_name: Ident,
+    // The function creating an empty builder, associated with `ty` unless the
+    // target is a function call
+    new_fn: Ident,
+    fields: &'a Fields,
+}
+
+enum TargetKind {
+    // The path to the struct or variant in a struct expression
+    Struct(TS),
+    Variant(TS),
+    // A call to `func` with the fields as its arguments, in order
+    Call {
+        func: TS,
+        args: Vec<Ident>,
+        asyncness: Option<syn::token::Async>,
+        unsafety: Option<syn::token::Unsafe>,
+        method: Option<Box<Method>>,
+    },
+}
+
+// What the builder for a method needs beyond a function's
+struct Method {
+    self_ty: Type,
+    // The generics of the impl, which `new_fn` goes in
+    impl_generics: Generics,
+    // Those `new_fn` adds to the impl's: a lifetime for a borrowed receiver,
+    // those of elided argument lifetimes and the method's own
+    fn_generics: Generics,
+    // The receiver `new_fn` takes, like `&'__self self`, and the type of the
+    // builder's `__self` field holding it
+    receiver: Option<(TS, Type)>,
+}
+
+impl Target<'_> {
+    // Only a struct is a whole value which a builder can start from, and a
+    // variant or a function's arguments may not use every generic parameter.
+    fn is_struct(&self) -> bool {
+        matches!(self.kind, TargetKind::Struct(_))
+    }
+
+    // The type of the receiver a method's builder holds in `__self`
+    fn receiver(&self) -> Option<&Type> {
+        match &self.kind {
+            TargetKind::Call {
+                method: Some(method),
+                ..
+            } => method.receiver.as_ref().map(|(_, ty)| ty),
+            _ => None,
+        }
+    }
+
+    // The type a builder's `PhantomData` marker returns, which for a method
+    // includes the type of the impl, whose generics the builder shares
+    fn marker_ty(&self) -> TS {
+        let ty = &self.ty;
+        match &self.kind {
+            TargetKind::Call {
+                method: Some(method),
+                ..
+            } => {
+                let self_ty = &method.self_ty;
+                quote!((#self_ty, #ty))
+            }
+            _ => quote!(#ty),
+        }
+    }
+
+    // `async` or `unsafe` on `build` when the function it calls is
+    fn build_qualifiers(&self) -> TS {
+        match &self.kind {
+            TargetKind::Call {
+                asyncness,
+                unsafety,
+                ..
+            } => quote!(#asyncness #unsafety),
+            _ => quote! {},
+        }
+    }
+
+    // The target built from the locals bound by `build_fields`, whose struct
+    // initializers are `inits`
+    fn construct(&self, inits: &[TS]) -> TS {
+        match &self.kind {
+            TargetKind::Struct(ctor) | TargetKind::Variant(ctor) => quote! {
+                #ctor {
+                    #(#inits),*
+                }
+            },
+            TargetKind::Call {
+                func,
+                args,
+                asyncness,
+                ..
+            } => {
+                let receiver = self.receiver().map(|_| quote!(self.__self,));
+                let call = quote!(#func(#receiver #(#args),*));
+                match asyncness {
+                    Some(_) => quote!(#call.await),
+                    None => call,
+                }
+            }
+        }
+    }
+
+    // `new_fn` returning `body`, an empty builder of type `builder_ty`
+    fn new_fn_item(
+        &self,
+        generics: &Generics,
+        vis: &Visibility,
+        constness: &TS,
+        builder_ty: &TS,
+        body: TS,
+    ) -> TS {
+        let (impl_generics, _, where_clause) = generics.split_for_impl();
+        let new_fn = &self.new_fn;
+        let ty = &self.ty;
+        match &self.kind {
+            TargetKind::Call {
+                method: Some(method),
+                ..
+            } => {
+                let self_ty = &method.self_ty;
+                let (impl_generics, _, where_clause) = method.impl_generics.split_for_impl();
+                let (fn_generics, _, fn_where_clause) = method.fn_generics.split_for_impl();
+                let receiver = method.receiver.as_ref().map(|(receiver, _)| receiver);
+                quote! {
+                    impl #impl_generics #self_ty #where_clause {
+                        #vis #constness fn #new_fn #fn_generics(#receiver) -> #builder_ty #fn_where_clause {
+                            #body
+                        }
+                    }
+                }
+            }
+            TargetKind::Call { .. } => quote! {
+                #vis #constness fn #new_fn #impl_generics() -> #builder_ty #where_clause {
+                    #body
+                }
+            },
+            _ => quote! {
+                impl #impl_generics #ty #where_clause {
+                    #vis #constness fn #new_fn() -> #builder_ty {
+                        #body
+                    }
+                }
+            },
+        }
+    }
+
+    // The doc comment on `build`, listing the `required` fields it `needs`
+    // set, like "Fails unless these required".
+    fn build_doc(&self, required: &[&Ident], needs: &str) -> TS {
+        let (mut doc, what) = match &self.kind {
+            TargetKind::Struct(ctor) | TargetKind::Variant(ctor) => (
+                format!("Builds a new `{}`.", ctor.to_string().replace(' ', "")),
+                "fields",
+            ),
+            TargetKind::Call { func, .. } => (
+                format!(
+                    "Calls `{}` with the arguments set on this builder.",
+                    func.to_string().replace(' ', "")
+                ),
+                "arguments",
+            ),
+        };
+        if !required.is_empty() {
+            let names: Vec<_> = required
+                .iter()
+                .map(|id| format!("`{}`", id.to_string().trim_start_matches("r#")))
+                .collect();
+            doc.push_str(&format!(
+                "\n\n{} {} are set: {}.",
+                needs,
+                what,
+                names.join(", ")
+            ));
+        }
+        quote!(#[doc = #doc])
+    }
+
+    // A call to `new_fn`
+    fn new_call(&self) -> TS {
+        let new_fn = &self.new_fn;
+        let ty = &self.ty;
+        match &self.kind {
+            TargetKind::Call {
+                method: Some(method),
+                ..
+            } => {
+                let self_ty = &method.self_ty;
+                quote!(<#self_ty>::#new_fn())
+            }
+            TargetKind::Call { .. } => quote!(#new_fn()),
+            _ => quote!(<#ty>::#new_fn()),
+        }
+    }
+}
+
+fn derive_builder(input: DeriveInput) -> Result<TS, Errors> {
+    let ty_name = &input.ident;
+    let (_, ty_generics, _) = input.generics.split_for_impl();
+    let ty = quote!(#ty_name #ty_generics);
+    let mut errors = Errors::default();
+    let opts = container_opts(&input.attrs, &input.vis, &mut errors);
+    // A const setter replaces the field's old value, and a `const fn` can't
+    // drop a value whose type might need dropping.
+    if opts.is_const {
+        if let Some(param) = input.generics.type_params().next() {
+            errors.push(syn::Error::new_spanned(
+                &param.ident,
+                "a `const` builder can't drop the values it replaces, so it can't hold type parameters",
+            ));
+        }
+    }
+
+    let mut targets = Vec::new();
+    match &input.data {
+        // Tuple struct fields are built with `Foo { 0: .., 1: .. }` just like
+        // named ones.
+        Data::Struct(ds) => match &ds.fields {
+            Fields::Unit => errors.push(syn::Error::new_spanned(
+                ty_name,
+                "Builder needs a struct with fields to set",
+            )),
+            fields => targets.push(Target {
+                ty: ty.clone(),
+                kind: TargetKind::Struct(quote!(#ty_name)),
+                builder_name: Ident::new(&format!("{}Builder", ty_name), ty_name.span()),
+                error_name: Ident::new(&format!("{}BuilderError", ty_name), ty_name.span()),
+                new_fn: Ident::new("builder", Span::call_site()),
+                fields,
+            }),
+        },
+        // Each variant with fields gets a builder of its own, and unit
+        // variants have nothing to build.
+        Data::Enum(data) => {
+            if opts.default {
+                errors.push(syn::Error::new_spanned(
+                    ty_name,
+                    "a container level `default` needs a struct to take defaults from",
+                ));
+            }
+            for variant in data.variants.iter() {
+                if let Some(att) = variant
+                    .attrs
+                    .iter()
+                    .find(|att| att.path.is_ident("builder"))
+                {
+                    errors.push(syn::Error::new_spanned(
+                        att,
+                        "builder attributes belong on the enum or on variant fields",
+                    ));
+                }
+                if let Fields::Unit = variant.fields {
+                    continue;
+                }
+                let name = variant.ident.to_string();
+                let name = name.trim_start_matches("r#");
+                let span = variant.ident.span();
+                targets.push(Target {
+                    ty: ty.clone(),
+                    kind: {
+                        let variant = &variant.ident;
+                        TargetKind::Variant(quote!(#ty_name::#variant))
+                    },
+                    builder_name: Ident::new(&format!("{}{}Builder", ty_name, name), span),
+                    error_name: Ident::new(&format!("{}{}BuilderError", ty_name, name), span),
+                    new_fn: Ident::new(&format!("{}_builder", snake_case(name)), span),
+                    fields: &variant.fields,
+                });
+            }
+        }
+        Data::Union(data) => errors.push(syn::Error::new_spanned(
+            data.union_token,
+            "Builder can only be derived for structs and enums",
+        )),
+    };
+
+    let targets: Vec<_> = targets
+        .iter()
+        .map(|target| {
+            // Skipped fields have no place on the builder and only matter to
+            // `build`.
+            let (skipped, fields): (Vec<_>, Vec<_>) = target
+                .fields
+                .iter()
+                .enumerate()
+                .map(|(index, f)| builder_field(f, index, &opts, &mut errors))
+                .partition(|f| f.skip);
+            if !opts.typestate && !opts.is_const {
+                check_error_variants(&fields, &opts, &mut errors);
+            }
+            check_method_names(&fields, &opts, &mut errors);
+            (target, fields, skipped)
+        })
+        .collect();
+    errors.finish()?;
+
+    let generics = &input.generics;
+    Ok(targets
+        .iter()
+        .map(|(target, fields, skipped)| {
+            if opts.typestate {
+                typestate_builder(target, generics, &opts, fields, skipped)
+            } else {
+                struct_builder(target, generics, &opts, fields, skipped)
+            }
+        })
+        .collect())
+}
+
+#[proc_macro_attribute]
+pub fn builder(args: TokenStream, item: TokenStream) -> TokenStream {
+    match builder_fn(args.into(), item.into()) {
+        Ok(s) => s.into(),
+        Err(errors) => errors.to_compile_errors().into(),
+    }
+}
+
+// A function parameter along with its attributes, which syn doesn't parse
+struct Param {
+    attrs: Vec<Attribute>,
+    arg: FnArg,
+}
+
+impl Parse for Param {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        Ok(Param {
+            attrs: input.call(Attribute::parse_outer)?,
+            arg: input.parse()?,
+        })
+    }
+}
+
+// Takes the attributes off the parameters of the function in `item`, so syn
+// can parse it, and returns them in the order of the parameters.  The
+// parameter list is the first parenthesized group after `fn` that isn't inside
+// the generics.
+fn take_param_attrs(item: TS) -> syn::Result<(TS, Vec<Vec<Attribute>>)> {
+    let mut tokens: Vec<TokenTree> = item.into_iter().collect();
+    let mut seen_fn = false;
+    let mut depth = 0;
+    let mut after_dash = false;
+    let found = tokens.iter().position(|tt| {
+        let found = match tt {
+            TokenTree::Ident(ident) if ident == "fn" => {
+                seen_fn = true;
+                false
+            }
+            TokenTree::Punct(p) if p.as_char() == '<' => {
+                depth += 1;
+                false
+            }
+            // The `>` of an `->` in a bound like `F: Fn() -> T` closes nothing
+            TokenTree::Punct(p) if p.as_char() == '>' && !after_dash => {
+                depth -= 1;
+                false
+            }
+            TokenTree::Group(g) => seen_fn && depth == 0 && g.delimiter() == Delimiter::Parenthesis,
+            _ => false,
+        };
+        after_dash = match tt {
+            TokenTree::Punct(p) => p.as_char() == '-' && p.spacing() == Spacing::Joint,
+            _ => false,
+        };
+        found
+    });
+    let index = match found {
+        Some(index) => index,
+        None => return Ok((tokens.into_iter().collect(), Vec::new())),
+    };
+    let group = match &tokens[index] {
+        TokenTree::Group(g) => g.clone(),
+        _ => unreachable!(),
+    };
+    let params = Punctuated::<Param, Token![,]>::parse_terminated.parse2(group.stream())?;
+    let args = params.iter().map(|param| &param.arg);
+    let mut stripped = Group::new(Delimiter::Parenthesis, quote!(#(#args),*));
+    stripped.set_span(group.span());
+    tokens[index] = TokenTree::Group(stripped);
+    let attrs = params.into_iter().map(|param| param.attrs).collect();
+    Ok((tokens.into_iter().collect(), attrs))
+}
+
+// Like `take_param_attrs` for the methods marked `#[builder]` in an impl
+// block, in order.  Each item in the block ends with a `;` or, for a method,
+// its body.
+fn take_impl_param_attrs(item: TS) -> syn::Result<(TS, Vec<Vec<Vec<Attribute>>>)> {
+    let mut tokens: Vec<TokenTree> = item.into_iter().collect();
+    let body = match tokens.last() {
+        Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Brace => g.clone(),
+        _ => return Ok((tokens.into_iter().collect(), Vec::new())),
+    };
+    let mut items = vec![Vec::new()];
+    for tt in body.stream() {
+        let ends = match &tt {
+            TokenTree::Punct(p) => p.as_char() == ';',
+            TokenTree::Group(g) => g.delimiter() == Delimiter::Brace,
+            _ => false,
+        };
+        items.last_mut().unwrap().push(tt);
+        if ends {
+            items.push(Vec::new());
+        }
+    }
+    let mut stream = TS::new();
+    let mut attrs = Vec::new();
+    for item in items {
+        let marked = item.windows(2).any(|pair| match pair {
+            [TokenTree::Punct(p), TokenTree::Group(g)] => {
+                p.as_char() == '#'
+                    && g.delimiter() == Delimiter::Bracket
+                    && matches!(g.stream().into_iter().next(), Some(TokenTree::Ident(ident)) if ident == "builder")
+            }
+            _ => false,
+        });
+        let item: TS = item.into_iter().collect();
+        if marked {
+            let (item, params) = take_param_attrs(item)?;
+            stream.extend(item);
+            attrs.push(params);
+        } else {
+            stream.extend(item);
+        }
+    }
+    let mut stripped = Group::new(Delimiter::Brace, stream);
+    stripped.set_span(body.span());
+    *tokens.last_mut().unwrap() = TokenTree::Group(stripped);
+    Ok((tokens.into_iter().collect(), attrs))
+}
+
+// Calls `f` on every lifetime in `ty`, with an elided one given as `'_`, up to
+// any `impl Trait` which is an error.  Function pointers and `Fn` bounds name
+// lifetimes of their own, so they are left alone.
+fn visit_lifetimes(ty: &mut Type, f: &mut dyn FnMut(&mut Lifetime)) -> syn::Result<()> {
+    match ty {
+        Type::Reference(r) => {
+            let span = r.and_token.spans[0];
+            f(r.lifetime.get_or_insert_with(|| Lifetime::new("'_", span)));
+            visit_lifetimes(&mut r.elem, f)
+        }
+        Type::Slice(s) => visit_lifetimes(&mut s.elem, f),
+        Type::Array(a) => visit_lifetimes(&mut a.elem, f),
+        Type::Ptr(p) => visit_lifetimes(&mut p.elem, f),
+        Type::Paren(p) => visit_lifetimes(&mut p.elem, f),
+        Type::Group(g) => visit_lifetimes(&mut g.elem, f),
+        Type::Tuple(t) => t
+            .elems
+            .iter_mut()
+            .try_for_each(|elem| visit_lifetimes(elem, f)),
+        Type::Path(p) => {
+            if let Some(qself) = &mut p.qself {
+                visit_lifetimes(&mut qself.ty, f)?;
+            }
+            for segment in p.path.segments.iter_mut() {
+                if let PathArguments::AngleBracketed(args) = &mut segment.arguments {
+                    for arg in args.args.iter_mut() {
+                        match arg {
+                            GenericArgument::Lifetime(lifetime) => f(lifetime),
+                            GenericArgument::Type(ty) => visit_lifetimes(ty, f)?,
+                            GenericArgument::Binding(binding) => {
+                                visit_lifetimes(&mut binding.ty, f)?
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+            Ok(())
+        }
+        Type::ImplTrait(_) => Err(syn::Error::new_spanned(
+            &ty,
+            "`impl Trait` arguments can't be held by a builder, use a generic parameter instead",
+        )),
+        _ => Ok(()),
+    }
+}
+
+// `#[builder]` on a function generates a typestate builder for its arguments,
+// which are treated just like the fields of a struct, and whose `call` passes
+// them to the function in order.  The builder holds references, so elided
+// lifetimes in the arguments are given names, and those in the return type
+// too when the arguments have just the one.  Nothing on a method can put the
+// builder outside its impl, so methods are handled by `builder_impl`.
+fn builder_fn(args: TS, item: TS) -> Result<TS, Errors> {
+    if is_impl(&item) {
+        return builder_impl(args, item);
+    }
+    let (item, param_attrs) = take_param_attrs(item)?;
+    let mut item: ItemFn = syn::parse2(item)?;
+    // A builder can't be declared inside an impl block, so a method's needs
+    // `#[builder]` on the impl too.  `self` or `Self` give a method away.
+    let decl = &item.decl;
+    let (generics, inputs, output) = (&decl.generics, &decl.inputs, &decl.output);
+    let where_clause = &generics.where_clause;
+    let self_span = inputs
+        .iter()
+        .find_map(|arg| match arg {
+            FnArg::SelfRef(_) | FnArg::SelfValue(_) => Some(arg.into_token_stream()),
+            _ => None,
+        })
+        .or_else(|| find_self_type(quote!(#generics #inputs #output #where_clause)));
+    if let Some(tokens) = self_span {
+        return Err(syn::Error::new_spanned(
+            tokens,
+            "a method's builder can't go inside its impl block, so put `#[builder]` on the impl as well",
+        )
+        .into());
+    }
+    let builder = fn_builder(
+        &args,
+        &item.vis,
+        &item.ident,
+        &item.decl,
+        (item.asyncness, item.unsafety),
+        param_attrs,
+        None,
+    )?;
+    item.attrs.retain(|att| !att.path.is_ident("builder"));
+    Ok(quote! {
+        #item
+        #builder
+    })
+}
+
+// The builder for a function or, given the type and generics of its impl, a
+// method, with the signature's `Self` already spelled out.
+fn fn_builder(
+    args: &TS,
+    vis: &Visibility,
+    fn_name: &Ident,
+    decl: &syn::FnDecl,
+    (asyncness, unsafety): (Option<syn::token::Async>, Option<syn::token::Unsafe>),
+    param_attrs: Vec<Vec<Attribute>>,
+    impl_of: Option<(&Type, &Generics)>,
+) -> Result<TS, Errors> {
+    let mut errors = Errors::default();
+
+    let mut attrs = vec![parse_quote!(#[builder(typestate, build_fn(name = "call"))])];
+    if !args.is_empty() {
+        attrs.extend(Attribute::parse_outer.parse2(quote!(#[builder(#args)]))?);
+    }
+    let opts = container_opts(&attrs, vis, &mut errors);
+    if opts.default {
+        errors.push(syn::Error::new_spanned(
+            args,
+            "a container level `default` needs a struct to take defaults from",
+        ));
+    }
+
+    let mut generics = decl.generics.clone();
+    let mut fresh = Vec::new();
+    let mut named = Vec::new();
+    let mut receiver = None;
+    let mut fields = Punctuated::<Field, Token![,]>::new();
+    for (arg, attrs) in decl.inputs.iter().zip(param_attrs) {
+        let (pat, ty) = match (arg, impl_of) {
+            (FnArg::Captured(captured), _) => (&captured.pat, &captured.ty),
+            // The builder holds the receiver, under a lifetime of its own if
+            // it's borrowed without one.
+            (FnArg::SelfRef(r), Some((self_ty, _))) => {
+                let lifetime = r
+                    .lifetime
+                    .clone()
+                    .unwrap_or_else(|| Lifetime::new("'__self", r.self_token.span));
+                let mutability = &r.mutability;
+                receiver = Some((
+                    quote!(&#lifetime #mutability self),
+                    parse_quote!(&#lifetime #mutability #self_ty),
+                    Some(lifetime),
+                ));
+                continue;
+            }
+            (FnArg::SelfValue(_), Some((self_ty, _))) => {
+                receiver = Some((quote!(self), (*self_ty).clone(), None));
+                continue;
+            }
+            _ => {
+                errors.push(syn::Error::new_spanned(
+                    arg,
+                    "`#[builder]` needs each argument to be a plain name",
+                ));
+                continue;
+            }
+        };
+        let ident = match pat {
+            Pat::Ident(p) if p.by_ref.is_none() && p.subpat.is_none() => p.ident.clone(),
+            _ => {
+                errors.push(syn::Error::new_spanned(
+                    pat,
+                    "`#[builder]` needs each argument to be a plain name",
+                ));
+                continue;
+            }
+        };
+        let mut ty = ty.clone();
+        let named_lifetimes = visit_lifetimes(&mut ty, &mut |lifetime| {
+            if lifetime.ident == "_" {
+                *lifetime = Lifetime::new(&format!("'__a{}", fresh.len()), lifetime.ident.span());
+                fresh.push(lifetime.clone());
+            }
+            named.push(lifetime.clone());
+        });
+        errors.check(named_lifetimes);
+        fields.push(Field {
+            attrs,
+            vis: Visibility::Inherited,
+            ident: Some(ident),
+            colon_token: Some(Default::default()),
+            ty,
+        });
+    }
+    for lifetime in fresh.into_iter().rev() {
+        generics
+            .params
+            .insert(0, GenericParam::Lifetime(LifetimeDef::new(lifetime)));
+    }
+    let mut ret = match &decl.output {
+        ReturnType::Default => parse_quote!(()),
+        ReturnType::Type(_, ty) => (**ty).clone(),
+    };
+    // Elided lifetimes in the result are the borrowed receiver's, or else
+    // the only argument lifetime's.
+    let elided = match &receiver {
+        Some((_, _, Some(lifetime))) => Some(lifetime),
+        _ => match named.as_slice() {
+            [only] => Some(only),
+            _ => None,
+        },
+    };
+    if let Some(elided) = elided {
+        let _ = visit_lifetimes(&mut ret, &mut |lifetime| {
+            if lifetime.ident == "_" {
+                *lifetime = elided.clone();
+            }
+        });
+    }
+
+    let fields = Fields::Named(FieldsNamed {
+        brace_token: Default::default(),
+        named: fields,
+    });
+    let snake = fn_name.to_string();
+    let snake = snake.trim_start_matches("r#");
+    let mut name = camel_case(snake);
+
+    // A method's builder shares the generics of the impl, with those of the
+    // method, and is named after the type as well, since other types may
+    // have methods of the same name.
+    let (func, method) = match impl_of {
+        Some((self_ty, impl_generics)) => {
+            let type_name = match self_ty {
+                Type::Path(tp) if tp.qself.is_none() => tp
+                    .path
+                    .segments
+                    .last()
+                    .map(|seg| seg.value().ident.to_string()),
+                _ => None,
+            };
+            match type_name {
+                Some(type_name) => name = format!("{}{}", type_name, name),
+                None => errors.push(syn::Error::new_spanned(
+                    self_ty,
+                    "`#[builder]` methods need an impl for a named type",
+                )),
+            }
+            let mut fn_generics = generics.clone();
+            let (receiver_arg, receiver_ty) = match receiver {
+                Some((arg, ty, lifetime)) => {
+                    if let Some(lifetime) = lifetime.filter(|l| l.ident == "__self") {
+                        fn_generics
+                            .params
+                            .insert(0, GenericParam::Lifetime(LifetimeDef::new(lifetime)));
+                    }
+                    (Some(arg), Some(ty))
+                }
+                None => (None, None),
+            };
+            let mut all = impl_generics.clone();
+            let predicates = fn_generics
+                .where_clause
+                .iter()
+                .flat_map(|w| w.predicates.iter().cloned());
+            all.make_where_clause().predicates.extend(predicates);
+            let (lifetimes, others): (Vec<_>, Vec<_>) = impl_generics
+                .params
+                .iter()
+                .chain(fn_generics.params.iter())
+                .cloned()
+                .partition(|param| matches!(param, GenericParam::Lifetime(_)));
+            all.params = lifetimes.into_iter().chain(others).collect();
+            generics = all;
+            (
+                quote!(<#self_ty>::#fn_name),
+                Some(Box::new(Method {
+                    self_ty: self_ty.clone(),
+                    impl_generics: impl_generics.clone(),
+                    fn_generics,
+                    receiver: receiver_arg.zip(receiver_ty),
+                })),
+            )
+        }
+        None => (quote!(#fn_name), None),
+    };
+    let target = Target {
+        ty: ret.into_token_stream(),
+        kind: TargetKind::Call {
+            func,
+            args: fields.iter().filter_map(|f| f.ident.clone()).collect(),
+            asyncness,
+            unsafety,
+            method,
+        },
+        builder_name: Ident::new(&format!("{}Builder", name), fn_name.span()),
+        error_name: Ident::new(&format!("{}BuilderError", name), fn_name.span()),
+        new_fn: Ident::new(&format!("{}_builder", snake), fn_name.span()),
+        fields: &fields,
+    };
+    let (skipped, builder_fields): (Vec<_>, Vec<_>) = fields
+        .iter()
+        .enumerate()
+        .map(|(index, f)| builder_field(f, index, &opts, &mut errors))
+        .partition(|f| f.skip);
+    check_method_names(&builder_fields, &opts, &mut errors);
+    errors.finish()?;
+
+    Ok(typestate_builder(
+        &target,
+        &generics,
+        &opts,
+        &builder_fields,
+        &skipped,
+    ))
+}
+
+// #[builder] on an inherent impl block gives each method in it marked
+// #[builder] a builder outside the impl, named after the type and the method
+// and started by `<method>_builder` on the type.  The builder for a method
+// taking `self` holds the receiver, so `client.send_builder().path("/").call()`
+// calls `client.send("/")`.
+fn builder_impl(args: TS, item: TS) -> Result<TS, Errors> {
+    if !args.is_empty() {
+        return Err(syn::Error::new_spanned(
+            args,
+            "options go on each method's own `#[builder(...)]`",
+        )
+        .into());
+    }
+    let (item, param_attrs) = take_impl_param_attrs(item)?;
+    let mut item: ItemImpl = syn::parse2(item)?;
+    if let Some((_, path, _)) = &item.trait_ {
+        return Err(syn::Error::new_spanned(
+            path,
+            "`#[builder]` methods go in an inherent impl, since a trait impl can't add the methods starting the builders",
+        )
+        .into());
+    }
+    let mut errors = Errors::default();
+    let mut builders = Vec::new();
+    let mut param_attrs = param_attrs.into_iter();
+    for impl_item in item.items.iter_mut() {
+        let method = match impl_item {
+            ImplItem::Method(method) => method,
+            _ => continue,
+        };
+        let att = match method
+            .attrs
+            .iter()
+            .position(|att| att.path.is_ident("builder"))
+        {
+            Some(index) => method.attrs.remove(index),
+            None => continue,
+        };
+        let param_attrs = param_attrs.next().unwrap_or_default();
+        let args = match builder_attr_args(&att) {
+            Ok(args) => args,
+            Err(e) => {
+                errors.push(e);
+                continue;
+            }
+        };
+        // Only the signature matters to the builder, and its `Self` means
+        // nothing outside the impl.
+        let mut sig = method.clone();
+        sig.block = parse_quote!({});
+        let sig = replace_self(sig.into_token_stream(), &item.self_ty);
+        let sig: ImplItemMethod = match syn::parse2(sig) {
+            Ok(sig) => sig,
+            Err(e) => {
+                errors.push(e);
+                continue;
+            }
+        };
+        let builder = fn_builder(
+            &args,
+            &sig.vis,
+            &sig.sig.ident,
+            &sig.sig.decl,
+            (sig.sig.asyncness, sig.sig.unsafety),
+            param_attrs,
+            Some((&item.self_ty, &item.generics)),
+        );
+        match builder {
+            Ok(builder) => builders.push(builder),
+            Err(e) => errors.append(e),
+        }
+    }
+    errors.finish()?;
+    Ok(quote! {
+        #item
+        #(#builders)*
+    })
+}
+
+// Whether an attribute macro's `item` is an impl block rather than a function
+fn is_impl(item: &TS) -> bool {
+    item.clone().into_iter().find_map(|tt| match tt {
+        TokenTree::Ident(ident) if ident == "impl" => Some(true),
+        TokenTree::Ident(ident) if ident == "fn" => Some(false),
+        _ => None,
+    }) == Some(true)
+}
+
+// The options inside a `#[builder(...)]` attribute, as the attribute macro
+// would be given them
+fn builder_attr_args(att: &Attribute) -> syn::Result<TS> {
+    let mut tts = att.tts.clone().into_iter();
+    match (tts.next(), tts.next()) {
+        (None, _) => Ok(TS::new()),
+        (Some(TokenTree::Group(g)), None) if g.delimiter() == Delimiter::Parenthesis => {
+            Ok(g.stream())
+        }
+        _ => Err(syn::Error::new_spanned(
+            att,
+            "expected `#[builder]` or `#[builder(...)]`",
+        )),
+    }
+}
+
+// The first `Self` in `tokens`, in a free function which must really be a
+// method
+fn find_self_type(tokens: TS) -> Option<TS> {
+    tokens.into_iter().find_map(|tt| match tt {
+        TokenTree::Ident(ident) if ident == "Self" => Some(ident.into_token_stream()),
+        TokenTree::Group(g) => find_self_type(g.stream()),
+        _ => None,
+    })
+}
+
+// `Self` in a method's signature spelled out as the impl's type, with a path
+// through it like `Self::Item` becoming `<Foo<T>>::Item`
+fn replace_self(tokens: TS, self_ty: &Type) -> TS {
+    let tokens: Vec<TokenTree> = tokens.into_iter().collect();
+    let mut replaced = TS::new();
+    for (i, tt) in tokens.iter().enumerate() {
+        match tt {
+            TokenTree::Ident(ident) if ident == "Self" => {
+                let in_path =
+                    matches!(tokens.get(i + 1), Some(TokenTree::Punct(p)) if p.as_char() == ':');
+                if in_path {
+                    replaced.extend(quote!(<#self_ty>));
+                } else {
+                    replaced.extend(self_ty.into_token_stream());
+                }
+            }
+            TokenTree::Group(g) => {
+                let mut group = Group::new(g.delimiter(), replace_self(g.stream(), self_ty));
+                group.set_span(g.span());
+                replaced.extend(Some(TokenTree::Group(group)));
+            }
+            tt => replaced.extend(Some(tt.clone())),
+        }
+    }
+    replaced
+}
+
+// A variant's fields, a function's arguments, or the fields left after
+// skipping some, may not use every generic parameter, so the builder marks
+// them as used with a `PhantomData`.
+fn needs_marker(target: &Target, generics: &Generics, skipped: &[BuilderField]) -> bool {
+    !generics.params.is_empty() && (!target.is_struct() || !skipped.is_empty())
+}
+
+// A where clause for a method whose bounds needn't hold for every builder, such
+// as a field type which can't be cloned, iterated or parsed.  A bound which
+// names no generic parameter is checked where it's written and fails to
+// compile if it doesn't hold, so each is made higher-ranked over an unused
+// lifetime, which defers the check to wherever the method is called.
+fn deferred_where(bounds: &[TS]) -> TS {
+    if bounds.is_empty() {
+        quote! {}
+    } else {
+        quote!(where #(for<'__a> #bounds),*)
+    }
+}
+
+// `From<Foo>` for a builder of type `builder_ty` with every field of the `Foo`
+// already set, and `Foo::to_builder` starting one from a clone.  A `Msg` may be
+// any of its variants, and a function's result is no record of its
+// arguments, so only struct builders have them.
+fn round_trip(
+    target: &Target,
+    generics: &Generics,
+    vis: &Visibility,
+    fields: &[BuilderField],
+    builder_ty: &TS,
+    extra_init: &TS,
+) -> TS {
+    if !target.is_struct() {
+        return quote! {};
+    }
+    let (impl_generics, _, where_clause) = generics.split_for_impl();
+    let struct_ty = &target.ty;
+    let builder_struct_name = &target.builder_name;
+    let inits = fields.iter().map(|f| {
+        let id = &f.id;
+        let member = &f.member;
+        if f.strips_option() {
+            quote!(#id: value.#member)
+        } else if f.sub_builder.is_some() {
+            quote!(#id: ::core::option::Option::Some(::core::convert::From::from(value.#member)))
+        } else {
+            quote!(#id: ::core::option::Option::Some(value.#member))
+        }
+    });
+    let to_builder_where = deferred_where(&[quote!(#struct_ty: ::core::clone::Clone)]);
+    quote! {
+        impl #impl_generics ::core::convert::From<#struct_ty> for #builder_ty #where_clause {
+            fn from(value: #struct_ty) -> Self {
+                #builder_struct_name {
+                    #(#inits,)*
+                    #extra_init
+                }
+            }
+        }
+
+        impl #impl_generics #struct_ty #where_clause {
+            #vis fn to_builder(&self) -> #builder_ty #to_builder_where {
+                ::core::convert::From::from(::core::clone::Clone::clone(self))
+            }
+        }
+    }
+}
+
+// `merge` layers another builder over this one, with every field set on
+// `other` taking its place, and `or` fills in what this builder leaves unset
+// from a fallback.  `each` collections which are set on both sides are
+// appended, unless the field asks for `merge = "replace"`.
+fn merge_method(vis: &Visibility, fields: &[BuilderField]) -> TS {
+    let mut bounds = Vec::new();
+    let merges: Vec<_> = fields
+        .iter()
+        .map(|f| {
+            let id = &f.id;
+            match &f.each {
+                _ if f.sub_builder.is_some() => quote! {
+                    if let ::core::option::Option::Some(theirs) = other.#id {
+                        match &mut self.#id {
+                            ::core::option::Option::Some(mine) => {
+                                mine.merge(theirs);
+                            }
+                            mine => *mine = ::core::option::Option::Some(theirs),
+                        }
+                    }
+                },
+                Some(each) if f.merge_append => {
+                    let ty = f.ty;
+                    let item_type = each.item_type();
+                    bounds.push(quote!(#ty: ::core::iter::IntoIterator<Item = #item_type>));
+                    quote! {
+                        if let ::core::option::Option::Some(theirs) = other.#id {
+                            match &mut self.#id {
+                                ::core::option::Option::Some(mine) => {
+                                    ::core::iter::Extend::extend(mine, theirs)
+                                }
+                                mine => *mine = ::core::option::Option::Some(theirs),
+                            }
+                        }
+                    }
+                }
+                _ => quote! {
+                    if other.#id.is_some() {
+                        self.#id = other.#id;
+                    }
+                },
+            }
+        })
+        .collect();
+    let merge_where = deferred_where(&bounds);
+    quote! {
+        #vis fn merge(&mut self, other: Self) -> &mut Self #merge_where {
+            #(#merges)*
+            self
+        }
+
+        #vis fn or(self, fallback: Self) -> Self #merge_where {
+            let mut fallback = fallback;
+            fallback.merge(self);
+            fallback
+        }
+    }
+}
+
+// `set_from_str` sets a field from its key and the text of its value, and
+// `from_env` does so for every field whose environment variable, the prefix
+// followed by the upper case key, is set.  Keys match ignoring ASCII case.  An
+// `each` field adds an item for every key, taking `key=value` for a map, and a
+// comma separated list from the environment.  A sub-builder, which needs
+// `from_kv` too, takes keys `<key>.<inner key>` and variables
+// `<PREFIX><KEY>_<INNER KEY>`.
+fn kv_methods(vis: &Visibility, error_name: &Ident, fields: &[BuilderField]) -> TS {
+    let mut bounds = Vec::new();
+    let mut parse = |ty: &Type, text: TS| {
+        bounds.push(quote!(#ty: ::core::str::FromStr));
+        bounds.push(quote!(<#ty as ::core::str::FromStr>::Err: ::core::fmt::Display));
+        quote! {
+            <#ty as ::core::str::FromStr>::from_str(#text).map_err(|e| #error_name::InvalidValue {
+                key: ::std::string::ToString::to_string(key),
+                error: ::std::string::ToString::to_string(&e),
+            })?
+        }
+    };
+    let mut sets = Vec::new();
+    let mut loads = Vec::new();
+    for f in fields {
+        let id = &f.id;
+        let key = &f.key;
+        let var = key.to_uppercase();
+        if let Some(sub) = &f.sub_builder {
+            let builder = &sub.builder;
+            let variant = invalid_variant(id);
+            let head = format!("{}.", key);
+            let len = head.len();
+            sets.push(quote! {
+                match key.get(..#len) {
+                    ::core::option::Option::Some(head) if head.eq_ignore_ascii_case(#head) => {
+                        return self
+                            .#id
+                            .get_or_insert_with(::core::default::Default::default)
+                            .set_from_str(&key[#len..], value)
+                            .map_err(#error_name::#variant);
+                    }
+                    _ => {}
+                }
+            });
+            let var = format!("{}_", var);
+            loads.push(quote! {
+                builder.#id = ::core::option::Option::Some(
+                    <#builder>::from_env(&::std::format!("{}{}", prefix, #var))
+                        .map_err(#error_name::#variant)?,
+                );
+            });
+            continue;
+        }
+        let set = match &f.each {
+            Some(each) => {
+                let item = if let [k, v] = each.items.as_slice() {
+                    let k = parse(k, quote!(k));
+                    let v = parse(v, quote!(v));
+                    quote! {
+                        match value.find('=') {
+                            ::core::option::Option::Some(i) => {
+                                let (k, v) = (&value[..i], &value[i + 1..]);
+                                (#k, #v)
+                            }
+                            ::core::option::Option::None => {
+                                return ::core::result::Result::Err(#error_name::InvalidValue {
+                                    key: ::std::string::ToString::to_string(key),
+                                    error: ::std::string::ToString::to_string("expected `key=value`"),
+                                })
+                            }
+                        }
+                    }
+                } else {
+                    parse(&each.items[0], quote!(value))
+                };
+                quote! {
+                    let item = #item;
+                    ::core::iter::Extend::extend(
+                        self.#id.get_or_insert_with(::core::default::Default::default),
+                        ::core::iter::once(item),
+                    );
+                }
             }
-            let iatt = &ml.nested[0];
-            let nv = match iatt {
-                NestedMeta::Meta(Meta::NameValue(nv)) => nv,
-                _ => Err(builder_error(&ml))?,
-            };
-            if nv.ident != "each" {
-                Err(builder_error(&ml))?;
+            None if f.is_optional() => {
+                let parsed = parse(optional_type(f.ty), quote!(value));
+                if f.strips_option() {
+                    quote!(self.#id = ::core::option::Option::Some(#parsed);)
+                } else {
+                    quote! {
+                        self.#id = ::core::option::Option::Some(::core::option::Option::Some(#parsed));
+                    }
+                }
             }
-            let s = match &nv.lit {
-                Lit::Str(s) => s,
-                _ => Err(builder_error(&ml))?,
-            };
-            let ident = Ident::new(&s.value(), s.span());
-            let inner = simple_inner_type(&f.ty, "Vec").expect("Expected Vec<T> in each=\"name\"");
-            return Ok(Some((ident, inner)));
+            None => {
+                let parsed = parse(f.ty, quote!(value));
+                quote!(self.#id = ::core::option::Option::Some(#parsed);)
+            }
+        };
+        sets.push(quote! {
+            if key.eq_ignore_ascii_case(#key) {
+                #set
+                return ::core::result::Result::Ok(());
+            }
+        });
+        let values = if f.each.is_some() {
+            quote!(value.split(','))
+        } else {
+            quote!(::core::iter::once(value.as_str()))
+        };
+        loads.push(quote! {
+            let var = ::std::format!("{}{}", prefix, #var);
+            match ::std::env::var(&var) {
+                ::core::result::Result::Ok(value) => {
+                    for value in #values {
+                        builder.set_from_str(#key, value)?;
+                    }
+                }
+                ::core::result::Result::Err(::std::env::VarError::NotPresent) => {}
+                ::core::result::Result::Err(e) => {
+                    return ::core::result::Result::Err(#error_name::InvalidValue {
+                        key: var,
+                        error: ::std::string::ToString::to_string(&e),
+                    })
+                }
+            }
+        });
+    }
+    let kv_where = deferred_where(&bounds);
+    quote! {
+        #vis fn set_from_str(&mut self, key: &str, value: &str) -> ::core::result::Result<(), #error_name> #kv_where {
+            #(#sets)*
+            ::core::result::Result::Err(#error_name::UnknownKey(::std::string::ToString::to_string(key)))
+        }
+
+        #vis fn from_env(prefix: &str) -> ::core::result::Result<Self, #error_name> #kv_where {
+            let mut builder: Self = ::core::default::Default::default();
+            #(#loads)*
+            ::core::result::Result::Ok(builder)
         }
     }
-    Ok(None)
 }
 
-fn field_is_builder_vec(f: &Field) -> bool {
-    match get_builder_name(f) {
-        Ok(Some(_)) => true,
-        _ => false,
-    }
+// The name serde deserializes a field by, from `#[serde(rename = "...")]` or
+// `#[serde(rename(deserialize = "..."))]`.  Malformed attributes are left for
+// serde's own derive to report.
+fn serde_rename(attrs: &[Attribute]) -> Option<String> {
+    let renamed = |lit: &Lit| match lit {
+        Lit::Str(s) => Some(s.value()),
+        _ => None,
+    };
+    let metas = attrs
+        .iter()
+        .filter(|att| att.path.is_ident("serde"))
+        .filter_map(|att| match att.parse_meta() {
+            Ok(Meta::List(ml)) => Some(ml.nested),
+            _ => None,
+        });
+    metas.flatten().find_map(|nested| match nested {
+        NestedMeta::Meta(Meta::NameValue(nv)) if nv.ident == "rename" => renamed(&nv.lit),
+        NestedMeta::Meta(Meta::List(ml)) if ml.ident == "rename" => {
+            ml.nested.iter().find_map(|nested| match nested {
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.ident == "deserialize" => {
+                    renamed(&nv.lit)
+                }
+                _ => None,
+            })
+        }
+        _ => None,
+    })
 }
 
-#[proc_macro_derive(Builder, attributes(builder))]
-pub fn derive(input: TokenStream) -> TokenStream {
-    let input = parse_macro_input!(input as DeriveInput);
-    match derive_builder(input) {
-        Ok(s) => s,
-        Err(s) => s,
+// #[builder(derive_deserialize)] implements serde's `Deserialize` for the
+// builder, reading a map with an entry for each field set, named by its key,
+// or the name `#[serde(rename = "...")]` gives it.  An `Option` field is read
+// as one, so `null` leaves it unset.  Unknown keys are ignored, an `each`
+// collection is read whole, and a sub-builder is read with its own
+// `Deserialize`, so it needs `derive_deserialize` too.  Required fields left
+// out are only missed by `build`, which lets partial configs be merged before
+// building.
+fn deserialize_impl(target: &Target, generics: &Generics, fields: &[BuilderField]) -> TS {
+    let builder_name = &target.builder_name;
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let mut de_generics = generics.clone();
+    de_generics.params.insert(
+        0,
+        GenericParam::Lifetime(LifetimeDef::new(Lifetime::new("'__de", Span::call_site()))),
+    );
+    let predicates = &mut de_generics.make_where_clause().predicates;
+    for f in fields {
+        let ty = f.stored_type();
+        predicates.push(parse_quote!(#ty: ::serde::Deserialize<'__de>));
     }
-}
+    let (de_impl_generics, _, de_where_clause) = de_generics.split_for_impl();
+
+    let variants: Vec<_> = (0..fields.len())
+        .map(|i| Ident::new(&format!("__field{}", i), Span::call_site()))
+        .collect();
+    let variants = &variants;
+    let keys: Vec<_> = fields.iter().map(|f| &f.key).collect();
+    let reads = fields.iter().zip(variants).map(|(f, variant)| {
+        let id = &f.id;
+        let key = &f.key;
+        let ty = f.stored_type();
+        let read = if f.strips_option() {
+            quote!(::serde::de::MapAccess::next_value::<::core::option::Option<#ty>>(&mut map)?)
+        } else {
+            quote! {
+                ::core::option::Option::Some(::serde::de::MapAccess::next_value::<#ty>(&mut map)?)
+            }
+        };
+        quote! {
+            __Key::#variant => {
+                if builder.#id.is_some() {
+                    return ::core::result::Result::Err(
+                        <__M::Error as ::serde::de::Error>::duplicate_field(#key),
+                    );
+                }
+                builder.#id = #read;
+            }
+        }
+    });
+    let expecting = format!("struct {}", builder_name);
+
+    // Keys are read as a `__Key`, like serde's own derive does, so they can be
+    // borrowed or owned.
+    let key = quote! {
+        enum __Key {
+            #(#variants,)*
+            __Ignore,
+        }
+
+        struct __KeyVisitor;
+
+        impl<'__de> ::serde::de::Visitor<'__de> for __KeyVisitor {
+            type Value = __Key;
+
+            fn expecting(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+                f.write_str("a field name")
+            }
+
+            fn visit_str<__E>(self, key: &str) -> ::core::result::Result<__Key, __E>
+            where
+                __E: ::serde::de::Error,
+            {
+                ::core::result::Result::Ok(match key {
+                    #(#keys => __Key::#variants,)*
+                    _ => __Key::__Ignore,
+                })
+            }
+        }
+
+        impl<'__de> ::serde::Deserialize<'__de> for __Key {
+            fn deserialize<__D>(deserializer: __D) -> ::core::result::Result<Self, __D::Error>
+            where
+                __D: ::serde::Deserializer<'__de>,
+            {
+                ::serde::Deserializer::deserialize_identifier(deserializer, __KeyVisitor)
+            }
+        }
+    };
+
+    let read_map = quote! {
+        let mut builder: Self::Value = ::core::default::Default::default();
+        while let ::core::option::Option::Some(key) =
+            ::serde::de::MapAccess::next_key::<__Key>(&mut map)?
+        {
+            match key {
+                #(#reads)*
+                __Key::__Ignore => {
+                    ::serde::de::MapAccess::next_value::<::serde::de::IgnoredAny>(&mut map)?;
+                }
+            }
+        }
+        ::core::result::Result::Ok(builder)
+    };
 
-fn derive_builder(input: DeriveInput) -> Result<TokenStream, TokenStream> {
+    // The visitor is declared with the builder's generics, since an item
+    // nested in `deserialize` can't use those of the impl.
+    let visitor = quote! {
+        struct __Visitor #impl_generics (
+            ::core::marker::PhantomData<fn() -> #builder_name #ty_generics>,
+        ) #where_clause;
 
-    let struct_name = input.ident;
+        impl #de_impl_generics ::serde::de::Visitor<'__de> for __Visitor #ty_generics #de_where_clause {
+            type Value = #builder_name #ty_generics;
 
-    let builder_struct_name = Ident::new(&format!("{}Builder", struct_name), struct_name.span());
+            fn expecting(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+                f.write_str(#expecting)
+            }
 
-    let ds = match input.data {
-        Data::Struct(ds) => ds,
-        _ => panic!("Unable to derive Builder unless it's a struct!"),
+            fn visit_map<__M>(self, mut map: __M) -> ::core::result::Result<Self::Value, __M::Error>
+            where
+                __M: ::serde::de::MapAccess<'__de>,
+            {
+                #read_map
+            }
+        }
     };
 
-    let fields = match ds.fields {
-        Fields::Named(nf) => nf,
-        _ => panic!("Unable to derive Builder unless it's a named field struct"),
+    quote! {
+        impl #de_impl_generics ::serde::Deserialize<'__de> for #builder_name #ty_generics #de_where_clause {
+            fn deserialize<__D>(deserializer: __D) -> ::core::result::Result<Self, __D::Error>
+            where
+                __D: ::serde::Deserializer<'__de>,
+            {
+                #key
+                #visitor
+                ::serde::Deserializer::deserialize_map(
+                    deserializer,
+                    __Visitor(::core::marker::PhantomData),
+                )
+            }
+        }
+    }
+}
+
+// The builder for `target`, along with its error type.
+fn struct_builder(
+    target: &Target,
+    generics: &Generics,
+    opts: &ContainerOpts,
+    fields: &[BuilderField],
+    skipped: &[BuilderField],
+) -> TS {
+    let builder_struct_name = &target.builder_name;
+    let error_name = &target.error_name;
+    let vis = &opts.vis;
+    let constness = if opts.is_const {
+        quote!(const)
+    } else {
+        quote! {}
     };
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let struct_ty = &target.ty;
 
-    let bits = fields.named.iter().map(|f| {
-        let id = &f.ident;
-        let ty = &f.ty;
-        if field_is_optional(&ty) {
+    let bits = fields.iter().map(|f| {
+        let id = &f.id;
+        let ty = f.ty;
+        if f.strips_option() {
             quote! {
                 #id: #ty
             }
         } else {
+            let ty = f.stored_type();
             quote! {
-                #id : std::option::Option<#ty>
+                #id : ::core::option::Option<#ty>
             }
         }
     });
 
+    let (marker_field, marker_init) = if needs_marker(target, generics, skipped) {
+        (
+            quote!(__marker: ::core::marker::PhantomData<fn() -> #struct_ty>,),
+            quote!(__marker: ::core::marker::PhantomData,),
+        )
+    } else {
+        (quote! {}, quote! {})
+    };
+    let marker_init = &marker_init;
+
+    let derives = derive_attr(opts);
+    let new_call = target.new_call();
     let builder_struct = quote! {
-        pub struct #builder_struct_name {
-            #(#bits),*
+        #derives
+        #vis struct #builder_struct_name #generics #where_clause {
+            #(#bits,)*
+            #marker_field
+        }
+
+        impl #impl_generics ::core::default::Default for #builder_struct_name #ty_generics #where_clause {
+            fn default() -> Self {
+                #new_call
+            }
+        }
+    };
+
+    let inits = fields.iter().map(|f| {
+        let id = &f.id;
+        quote! {
+            #id : ::core::option::Option::None
         }
+    });
+
+    let builder_impl = target.new_fn_item(
+        generics,
+        vis,
+        &constness,
+        &quote!(#builder_struct_name #ty_generics),
+        quote! {
+            #builder_struct_name {
+                #(#inits,)*
+                #marker_init
+            }
+        },
+    );
+
+    let round_trip = round_trip(
+        target,
+        generics,
+        vis,
+        fields,
+        &quote!(#builder_struct_name #ty_generics),
+        marker_init,
+    );
+
+    // Without a `&mut self` to update in place, the immutable pattern clones
+    // the whole builder in every setter.
+    let field_ids: Vec<_> = fields.iter().map(|f| &f.id).collect();
+    let (field_ids, field_values) = (&field_ids, &field_ids);
+    let clone_bounds: Vec<_> = if generics.params.is_empty() {
+        Vec::new()
+    } else {
+        fields
+            .iter()
+            .map(|f| {
+                let ty = f.stored_type();
+                quote!(#ty: ::core::clone::Clone)
+            })
+            .collect()
+    };
+    let clone_bounds = &clone_bounds;
+    let setters = Setters {
+        pattern: opts.pattern,
+        constness: constness.clone(),
+        clone_self: quote! {
+            #builder_struct_name {
+                #(#field_ids: ::core::clone::Clone::clone(&self.#field_values),)*
+                #marker_init
+            }
+        },
+        clone_where: if clone_bounds.is_empty() {
+            quote! {}
+        } else {
+            quote!(where #(#clone_bounds),*)
+        },
     };
+    let recv = setters.receiver();
+
+    let builder_methods = fields.iter().map(|f| {
+        let id = &f.id;
+        let clear = setters.method(
+            &f.setter_vis,
+            &prefixed("clear_", id),
+            quote! {},
+            quote! {},
+            quote!(#recv.#id = ::core::option::Option::None;),
+        );
+        // Rather than a setter, a sub-builder field has a method handing out
+        // the inner builder to be set in place, whatever the pattern.
+        if let Some(sub) = &f.sub_builder {
+            let vis = &f.setter_vis;
+            let builder = &sub.builder;
+            let docs = &f.docs;
+            let name = &f.setter_name;
+            return quote! {
+                #(#docs)*
+                #vis fn #name(&mut self) -> &mut #builder {
+                    self.#id.get_or_insert_with(::core::default::Default::default)
+                }
 
-    let inits = fields.named.iter().map(|f| {
-        let id = &f.ident;
-        if field_is_builder_vec(&f) {
+                #clear
+            };
+        }
+        let store = |value: &TS| quote!(#recv.#id = ::core::option::Option::Some(#value););
+        let append = |value: &TS| {
             quote! {
-                #id : std::option::Option::Some(std::vec::Vec::new())
+                ::core::iter::Extend::extend(
+                    #recv.#id.get_or_insert_with(::core::default::Default::default),
+                    #value,
+                );
             }
+        };
+        let store: &dyn Fn(&TS) -> TS = if f.append { &append } else { &store };
+        let mut main = setters.setter(f, f.setter_type(), store);
+        let try_setter = if f.try_setter {
+            setters.try_setter(f, f.setter_type(), store)
         } else {
+            quote! {}
+        };
+
+        let methods = if let Some(each) = &f.each {
+            if f.setter_name == each.name {
+                main = quote! {};
+            }
+            let each = each_setters(
+                &setters,
+                f,
+                each,
+                &quote!(#recv.#id.get_or_insert_with(::core::default::Default::default)),
+            );
             quote! {
-                #id : std::option::Option::None
+                #each
+                #main
             }
+        } else {
+            main
+        };
+        quote! {
+            #methods
+            #try_setter
+            #clear
         }
     });
 
-    let builder_impl = quote! {
-        impl #struct_name {
-            pub fn builder() -> #builder_struct_name {
-                #builder_struct_name {
-                    #(#inits),*
+    let required: Vec<_> = fields
+        .iter()
+        .filter(|f| f.is_required())
+        .map(|f| &f.id)
+        .collect();
+    let missing_variants: Vec<_> = required.iter().map(|id| missing_variant(id)).collect();
+    let missing_variants = &missing_variants;
+    let missing_arms: Vec<_> = required
+        .iter()
+        .zip(missing_variants.iter())
+        .map(|(id, variant)| {
+            let message = format!("Missing field {}", id.to_string().trim_start_matches("r#"));
+            quote! {
+                #error_name::#variant => f.write_str(#message),
+            }
+        })
+        .collect();
+
+    let subs: Vec<_> = fields
+        .iter()
+        .filter_map(|f| f.sub_builder.as_ref().map(|sub| (&f.id, sub)))
+        .collect();
+    let invalid_variants: Vec<_> = subs
+        .iter()
+        .map(|(id, sub)| {
+            let variant = invalid_variant(id);
+            let error = &sub.error;
+            quote!(#variant(#error))
+        })
+        .collect();
+    let invalid_arms = subs.iter().map(|(id, _)| {
+        let variant = invalid_variant(id);
+        let prefix = format!("{}: ", id.to_string().trim_start_matches("r#"));
+        quote! {
+            #error_name::#variant(e) => {
+                f.write_str(#prefix)?;
+                ::core::fmt::Display::fmt(e, f)
+            }
+        }
+    });
+    // Without `std` the error trait comes from `core`, and a validation
+    // message is a `&'static str` since there may be no allocator.
+    let (error_trait, message) = if opts.no_std {
+        (quote!(::core::error::Error), quote!(&'static str))
+    } else {
+        (quote!(::std::error::Error), quote!(::std::string::String))
+    };
+    let error_source = if subs.is_empty() {
+        quote! {}
+    } else {
+        let source_arms = subs.iter().map(|(id, _)| {
+            let variant = invalid_variant(id);
+            quote!(#error_name::#variant(e) => ::core::option::Option::Some(e),)
+        });
+        quote! {
+            fn source(&self) -> ::core::option::Option<&(dyn #error_trait + 'static)> {
+                match self {
+                    #(#source_arms)*
+                    _ => ::core::option::Option::None,
                 }
             }
         }
     };
 
-    let builder_methods: Result<Vec<_>, TokenStream> = fields
-        .named
-        .iter()
-        .map(|f| {
-            let id = f.ident.as_ref().unwrap();
-            let ty = &f.ty;
-            if field_is_optional(&ty) {
-                let ty = optional_type(&ty);
-                Ok(quote! {
-                    fn #id ( &mut self, #id : #ty ) -> &mut Self {
-                        self.#id = std::option::Option::Some(#id);
-                        self
-                    }
+    let (kv_variants, kv_arms) = if opts.from_kv {
+        (
+            quote! {
+                UnknownKey(::std::string::String),
+                InvalidValue {
+                    key: ::std::string::String,
+                    error: ::std::string::String,
+                },
+            },
+            quote! {
+                #error_name::UnknownKey(key) => {
+                    f.write_str("Unknown key ")?;
+                    f.write_str(key)
+                }
+                #error_name::InvalidValue { key, error } => {
+                    f.write_str("Invalid value for ")?;
+                    f.write_str(key)?;
+                    f.write_str(": ")?;
+                    f.write_str(error)
+                }
+            },
+        )
+    } else {
+        (quote! {}, quote! {})
+    };
 
-                })
+    let error_enum = quote! {
+        #[derive(Debug, Clone, PartialEq)]
+        #vis enum #error_name {
+            #(#missing_variants,)*
+            #(#invalid_variants,)*
+            #kv_variants
+            Validation(#message),
+        }
+
+        impl ::core::fmt::Display for #error_name {
+            fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+                match self {
+                    #(#missing_arms)*
+                    #(#invalid_arms)*
+                    #kv_arms
+                    #error_name::Validation(msg) => f.write_str(msg),
+                }
+            }
+        }
+
+        impl #error_trait for #error_name {
+            #error_source
+        }
+
+        impl ::core::convert::From<#message> for #error_name {
+            fn from(msg: #message) -> Self {
+                #error_name::Validation(msg)
+            }
+        }
+    };
+
+    let owned = opts.pattern == Pattern::Owned;
+    let all_fields: Vec<_> = fields.iter().chain(skipped).collect();
+    let (build_lets, build_inits) = build_fields(
+        &all_fields,
+        opts.pattern,
+        &|id| {
+            if opts.is_const {
+                let message = format!("Missing field {}", id.to_string().trim_start_matches("r#"));
+                quote!(::core::panic!(#message))
             } else {
-                // Non-optional field, so process attribute
-                let mut main = quote! {
-                        fn #id ( &mut self, #id : #ty ) -> &mut Self {
-                            self.#id = std::option::Option::Some(#id);
-                            self
-                        }
-                };
+                let variant = missing_variant(id);
+                quote!(::core::result::Result::Err(#error_name::#variant)?)
+            }
+        },
+        &|id| {
+            let variant = invalid_variant(id);
+            quote!(#error_name::#variant)
+        },
+    );
+    let default_init = container_default(struct_ty, &all_fields);
 
-                if let Some((bname, btype)) = get_builder_name(f)? {
-                    if id == &bname {
-                        main = quote! {};
-                    }
-                    Ok(quote! {
-                        fn #bname ( &mut self, #bname: #btype) -> &mut Self {
-                            let mref = self.#id.as_mut().unwrap();
-                            mref.push(#bname);
-                            self
+    // Unless it can move them, `build` clones every field out of the builder,
+    // which for a generic struct needs bounds beyond those the struct itself
+    // declares.  A mutable `build` builds a sub-builder in place, cloning it
+    // only for an inner `build` which takes it by value.
+    let mut build_bounds = Vec::new();
+    if !owned && !generics.params.is_empty() {
+        build_bounds.extend(
+            fields
+                .iter()
+                .filter(|f| match &f.sub_builder {
+                    Some(sub) => sub.owned || opts.pattern != Pattern::Mutable,
+                    None => true,
+                })
+                .map(|f| {
+                    let ty = f.stored_type();
+                    quote!(#ty: ::core::clone::Clone)
+                }),
+        );
+    }
+    if !generics.params.is_empty() && all_fields.iter().any(|f| f.uses_container_default()) {
+        build_bounds.push(quote!(#struct_ty: ::core::default::Default));
+    }
+    let build_where = if build_bounds.is_empty() {
+        quote! {}
+    } else {
+        quote!(where #(#build_bounds),*)
+    };
+    let build_self = match opts.pattern {
+        Pattern::Mutable => quote!(&mut self),
+        Pattern::Owned => quote!(self),
+        Pattern::Immutable => quote!(&self),
+    };
+
+    // Validation runs once every required field is known to be set, and its
+    // error converts into the build error like a missing field does.
+    let validate = match &opts.build_fn.validate {
+        Some(path) => {
+            let missing_checks =
+                required
+                    .iter()
+                    .zip(missing_variants.iter())
+                    .map(|(id, variant)| {
+                        quote! {
+                            if self.#id.is_none() {
+                                ::core::result::Result::Err(#error_name::#variant)?;
+                            }
                         }
-                        #main
-                    })
-                } else {
-                    Ok(main)
-                }
+                    });
+            let arg = if owned { quote!(&self) } else { quote!(self) };
+            quote! {
+                #(#missing_checks)*
+                #path(#arg)?;
+            }
+        }
+        None => quote! {},
+    };
+    let build_name = &opts.build_fn.name;
+    let build_error = match &opts.build_fn.error {
+        Some(ty) => quote!(#ty),
+        None => quote!(#error_name),
+    };
+
+    let qualifiers = target.build_qualifiers();
+    let construct = target.construct(&build_inits);
+    // A const `build` can't return an error for a missing field, but
+    // panicking fails the const evaluation with a message naming it.
+    let build_method = if opts.is_const {
+        let build_doc = target.build_doc(&required, "Panics unless these required");
+        quote! {
+            #build_doc
+            #vis const fn #build_name(self) -> #struct_ty {
+                #(#build_lets)*
+                #construct
+            }
+        }
+    } else {
+        let build_doc = target.build_doc(&required, "Fails unless these required");
+        quote! {
+            #build_doc
+            #vis #qualifiers fn #build_name(#build_self) -> ::core::result::Result<#struct_ty, #build_error> #build_where {
+                #validate
+                #default_init
+                #(#build_lets)*
+                ::core::result::Result::Ok(#construct)
             }
+        }
+    };
+
+    let merge_method = merge_method(vis, fields);
+    let getters = getters(fields);
+    let kv_methods = if opts.from_kv {
+        kv_methods(vis, error_name, fields)
+    } else {
+        quote! {}
+    };
+
+    let builder_struct_impl = quote! {
+        impl #impl_generics #builder_struct_name #ty_generics #where_clause {
+            #(#builder_methods)*
+            #(#getters)*
+            #merge_method
+            #kv_methods
+            #build_method
+        }
+    };
+
+    // Nothing a const builder does can fail, so it has no error type.
+    let error_enum = if opts.is_const {
+        quote! {}
+    } else {
+        error_enum
+    };
+
+    let deserialize_impl = if opts.derive_deserialize {
+        deserialize_impl(target, generics, fields)
+    } else {
+        quote! {}
+    };
+
+    quote! {
+        #builder_struct
+        #error_enum
+        #builder_impl
+        #round_trip
+        #builder_struct_impl
+        #deserialize_impl
+    }
+}
+
+// In typestate mode every required field gets a type parameter on the builder
+// which is either `Unset` or `Set`.  The setter for a required field moves the
+// builder into one whose parameter for that field is `Set`, and `build` is
+// only implemented once every parameter is `Set`, so a missing field becomes
+// a type error rather than a runtime one.
+fn typestate_builder(
+    target: &Target,
+    generics: &Generics,
+    opts: &ContainerOpts,
+    fields: &[BuilderField],
+    skipped: &[BuilderField],
+) -> TS {
+    let builder_struct_name = &target.builder_name;
+    let vis = &opts.vis;
+    let state_mod = Ident::new(
+        &format!("{}_state", snake_case(&builder_struct_name.to_string())),
+        builder_struct_name.span(),
+    );
+
+    let required: Vec<_> = fields
+        .iter()
+        .filter(|f| f.is_required())
+        .map(|f| &f.id)
+        .collect();
+    let params: Vec<_> = required
+        .iter()
+        .map(|id| {
+            Ident::new(
+                &format!("__{}", camel_case(&id.to_string())),
+                Span::call_site(),
+            )
         })
         .collect();
-    let builder_methods = builder_methods?;
+    let params = &params;
 
-    let build_method_fields = fields.named.iter().map(|f| {
-        let id = f.ident.as_ref().unwrap();
-        let id_str = id.to_string();
-        let ty = &f.ty;
-        if field_is_optional(&ty) {
-            quote! {
-                #id : self.#id.as_ref().map(|f| f.clone())
-            }
-        } else if field_is_builder_vec(&f) {
+    // The builder takes the struct's own generics followed by one parameter
+    // per required field.  Defaults are dropped since the state parameters
+    // which follow them have none.
+    let (impl_generics, _, where_clause) = generics.split_for_impl();
+    let struct_ty = &target.ty;
+    let struct_args = generic_args(generics);
+    let struct_args = &struct_args;
+    let mut state_generics = generics.clone();
+    for param in state_generics.params.iter_mut() {
+        if let GenericParam::Type(tp) = param {
+            tp.eq_token = None;
+            tp.default = None;
+        }
+    }
+    state_generics.params.extend(
+        params
+            .iter()
+            .cloned()
+            .map(|p| GenericParam::Type(TypeParam::from(p))),
+    );
+    let (state_impl_generics, state_ty_generics, _) = state_generics.split_for_impl();
+    let state_params = &state_generics.params;
+
+    let unset: Vec<_> = params.iter().map(|_| quote!(#state_mod::Unset)).collect();
+    let unset = &unset;
+    let set: Vec<_> = params.iter().map(|_| quote!(#state_mod::Set)).collect();
+    let set = &set;
+
+    let bits = fields.iter().map(|f| {
+        let id = &f.id;
+        let ty = f.ty;
+        if f.strips_option() {
             quote! {
-                #id : self.#id.as_ref().unwrap().clone()
+                #id: #ty
             }
         } else {
             quote! {
-                #id : self.#id
-                          .as_ref()
-                          .map(|f| f.clone())
-                          .ok_or_else(|| concat!("Missing field ", #id_str).to_owned())?
+                #id: ::core::option::Option<#ty>
             }
         }
     });
 
-    let build_method = quote! {
-        fn build(&mut self) -> std::result::Result<#struct_name, std::boxed::Box<dyn std::error::Error>> {
-            std::result::Result::Ok(#struct_name {
-                #(#build_method_fields),*
-            })
+    let marker = if needs_marker(target, generics, skipped) {
+        let marker_ty = target.marker_ty();
+        quote!(fn() -> #marker_ty,)
+    } else {
+        quote! {}
+    };
+
+    // A method's builder holds its receiver, so it can't be made by `Default`.
+    let (self_field, self_init, self_move) = match target.receiver() {
+        Some(ty) => (
+            quote!(__self: #ty,),
+            quote!(__self: self,),
+            quote!(__self: self.__self,),
+        ),
+        None => (quote! {}, quote! {}, quote! {}),
+    };
+    let new_call = target.new_call();
+    let default_impl = if target.receiver().is_some() {
+        quote! {}
+    } else {
+        quote! {
+            impl #impl_generics ::core::default::Default for #builder_struct_name<#(#struct_args,)* #(#unset),*> #where_clause {
+                fn default() -> Self {
+                    #new_call
+                }
+            }
         }
     };
 
-    let builder_struct_impl = quote! {
-        impl #builder_struct_name {
-            #(#builder_methods)*
-            #build_method
+    // Anything derived on the builder is derived on the states too, so the
+    // bounds on the state parameters hold.
+    let derives = derive_attr(opts);
+    let derive_scope = if opts.derives.is_empty() {
+        quote! {}
+    } else {
+        quote! {
+            #[allow(unused_imports)]
+            use super::*;
+        }
+    };
+    let builder_struct = quote! {
+        #vis mod #state_mod {
+            #derive_scope
+
+            #derives
+            pub struct Unset;
+            #derives
+            pub struct Set;
+        }
+
+        #derives
+        #vis struct #builder_struct_name<#state_params> #where_clause {
+            #(#bits,)*
+            #self_field
+            __state: ::core::marker::PhantomData<(#marker #(#params,)*)>,
         }
+
+        #default_impl
+    };
+
+    let inits = fields.iter().map(|f| {
+        let id = &f.id;
+        quote! {
+            #id: ::core::option::Option::None
+        }
+    });
+
+    let builder_impl = target.new_fn_item(
+        generics,
+        vis,
+        &quote! {},
+        &quote!(#builder_struct_name<#(#struct_args,)* #(#unset),*>),
+        quote! {
+            #builder_struct_name {
+                #(#inits,)*
+                #self_init
+                __state: ::core::marker::PhantomData,
+            }
+        },
+    );
+
+    let getters = getters(fields);
+    let round_trip = round_trip(
+        target,
+        generics,
+        vis,
+        fields,
+        &quote!(#builder_struct_name<#(#struct_args,)* #(#set),*>),
+        &quote!(__state: ::core::marker::PhantomData,),
+    );
+
+    // Setters which leave the state alone work just like the owned pattern.
+    let setters = Setters {
+        pattern: Pattern::Owned,
+        clone_self: quote! {},
+        clone_where: quote! {},
+        constness: quote! {},
     };
 
-    Ok((quote! {
+    let mut builder_methods = Vec::new();
+    for f in fields.iter() {
+        let id = &f.id;
+        let vis = &f.setter_vis;
+        let store = |value: &TS| quote!(self.#id = ::core::option::Option::Some(#value););
+        let append = |value: &TS| {
+            quote! {
+                ::core::iter::Extend::extend(
+                    self.#id.get_or_insert_with(::core::default::Default::default),
+                    #value,
+                );
+            }
+        };
+        let store: &dyn Fn(&TS) -> TS = if f.append { &append } else { &store };
+        if let Some(each) = &f.each {
+            builder_methods.push(each_setters(
+                &setters,
+                f,
+                each,
+                &quote!(self.#id.get_or_insert_with(::core::default::Default::default)),
+            ));
+            if f.setter_name != each.name {
+                builder_methods.push(setters.setter(f, f.setter_type(), store));
+            }
+        } else if !f.is_required() {
+            builder_methods.push(setters.setter(f, f.setter_type(), store));
+        }
+        if f.try_setter && !f.is_required() {
+            builder_methods.push(setters.try_setter(f, f.setter_type(), store));
+        }
+        // Clearing a required field would have to take it back to `Unset`, so
+        // only fields which can be left unset can be cleared.
+        if !f.is_required() {
+            builder_methods.push(setters.method(
+                vis,
+                &prefixed("clear_", id),
+                quote! {},
+                quote! {},
+                quote!(self.#id = ::core::option::Option::None;),
+            ));
+        }
+        if f.is_required() {
+            // Required field, so the setter moves the builder into the state
+            // where this field's parameter is `Set`.
+            let next_params = required.iter().zip(params.iter()).map(|(rid, param)| {
+                if rid == &id {
+                    quote!(#state_mod::Set)
+                } else {
+                    quote!(#param)
+                }
+            });
+            let next = quote!(#builder_struct_name<#(#struct_args,)* #(#next_params),*>);
+            let moved = |value: &TS| {
+                let moves = fields.iter().map(|g| {
+                    let gid = &g.id;
+                    if gid == id {
+                        quote! {
+                            #gid: ::core::option::Option::Some(#value)
+                        }
+                    } else {
+                        quote! {
+                            #gid: self.#gid
+                        }
+                    }
+                });
+                quote! {
+                    #builder_struct_name {
+                        #(#moves,)*
+                        #self_move
+                        __state: ::core::marker::PhantomData,
+                    }
+                }
+            };
+            let param = Ident::new("__V", Span::call_site());
+            let (bound, arg_ty, value) = setter_arg(f, id, f.ty, &param);
+            let generics = bound.map(|bound| quote!(<#bound>));
+            let body = moved(&value);
+            let name = &f.setter_name;
+            let docs = &f.docs;
+            builder_methods.push(quote! {
+                #(#docs)*
+                #vis fn #name #generics(self, #id: #arg_ty) -> #next {
+                    #body
+                }
+            });
+            if f.try_setter {
+                let ty = f.ty;
+                let try_name = prefixed("try_", name);
+                let body = moved(&quote!(#id));
+                builder_methods.push(quote! {
+                    #(#docs)*
+                    #vis fn #try_name<__V: ::core::convert::TryInto<#ty>>(
+                        self,
+                        #id: __V,
+                    ) -> ::core::result::Result<#next, <__V as ::core::convert::TryInto<#ty>>::Error> {
+                        let #id = ::core::convert::TryInto::try_into(#id)?;
+                        ::core::result::Result::Ok(#body)
+                    }
+                });
+            }
+        }
+    }
+
+    let all_fields: Vec<_> = fields.iter().chain(skipped).collect();
+    let (build_lets, build_inits) = build_fields(
+        &all_fields,
+        Pattern::Owned,
+        &|_| quote!(::core::unreachable!()),
+        &|_| quote!(::core::unreachable!()),
+    );
+    let build_name = &opts.build_fn.name;
+    let qualifiers = target.build_qualifiers();
+    let construct = target.construct(&build_inits);
+    let build_doc = target.build_doc(&required, "Only available once these required");
+    let default_init = container_default(struct_ty, &all_fields);
+    let default_bound =
+        if !generics.params.is_empty() && all_fields.iter().any(|f| f.uses_container_default()) {
+            quote!(where #struct_ty: ::core::default::Default)
+        } else {
+            quote! {}
+        };
+
+    quote! {
         #builder_struct
         #builder_impl
-        #builder_struct_impl
-    })
-    .into())
+        #round_trip
+
+        impl #state_impl_generics #builder_struct_name #state_ty_generics #where_clause {
+            #(#builder_methods)*
+            #(#getters)*
+        }
+
+        impl #impl_generics #builder_struct_name<#(#struct_args,)* #(#set),*> #where_clause {
+            #build_doc
+            #vis #qualifiers fn #build_name(self) -> #struct_ty #default_bound {
+                #default_init
+                #(#build_lets)*
+                #construct
+            }
+        }
+    }
 }
@@ -0,0 +1,53 @@
+// The build method reports a missing required field through a dedicated error
+// enum, `CommandBuilderError`, rather than a boxed string. Each required field
+// gets its own `Missing<Field>` variant so callers can match on exactly which
+// field was absent, and a `Validation` variant carries any other failure.
+// Messages name the field as written, without any `r#`.
+
+use derive_builder::Builder;
+use std::error::Error;
+
+#[derive(Builder)]
+pub struct Command {
+    executable: String,
+    #[builder(each = "arg")]
+    args: Vec<String>,
+    current_dir: String,
+    timeout: Option<u32>,
+}
+
+#[derive(Builder)]
+pub struct Token {
+    r#type: String,
+}
+
+fn main() {
+    let err = Command::builder()
+        .executable("cargo".to_owned())
+        .build()
+        .err()
+        .unwrap();
+    assert_eq!(err, CommandBuilderError::MissingCurrentDir);
+    assert_eq!(err.to_string(), "Missing field current_dir");
+
+    let err = Command::builder().build().err().unwrap();
+    assert_eq!(err.clone(), CommandBuilderError::MissingExecutable);
+
+    let boxed: Box<dyn Error> = Box::new(err);
+    assert_eq!(boxed.to_string(), "Missing field executable");
+
+    let err = CommandBuilderError::Validation("bad timeout".to_owned());
+    assert_eq!(err.to_string(), "bad timeout");
+
+    let command = Command::builder()
+        .executable("cargo".to_owned())
+        .current_dir("..".to_owned())
+        .build()
+        .unwrap();
+    assert!(command.args.is_empty());
+    assert!(command.timeout.is_none());
+
+    let err = Token::builder().build().err().unwrap();
+    assert_eq!(err, TokenBuilderError::MissingType);
+    assert_eq!(err.to_string(), "Missing field type");
+}
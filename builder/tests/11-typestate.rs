@@ -0,0 +1,44 @@
+// With #[builder(typestate)] on the struct, the builder tracks which required
+// fields have been set in its type. Each required field gets a type parameter
+// which starts out as `Unset` and becomes `Set` once its setter is called, and
+// `build` only exists once every required field is `Set`. Because a missing
+// field can no longer happen at runtime, `build` returns the struct directly.
+//
+//     impl CommandBuilder<command_builder_state::Set, command_builder_state::Set> {
+//         fn build(self) -> Command {
+//             ...
+//         }
+//     }
+
+use derive_builder::Builder;
+
+#[derive(Builder)]
+#[builder(typestate)]
+pub struct Command {
+    executable: String,
+    #[builder(each = "arg")]
+    args: Vec<String>,
+    env: Vec<String>,
+    current_dir: Option<String>,
+}
+
+fn main() {
+    let command = Command::builder()
+        .arg("build".to_owned())
+        .executable("cargo".to_owned())
+        .current_dir("..".to_owned())
+        .arg("--release".to_owned())
+        .env(vec![])
+        .build();
+
+    assert_eq!(command.executable, "cargo");
+    assert_eq!(command.args, vec!["build", "--release"]);
+    assert!(command.env.is_empty());
+    assert_eq!(command.current_dir, Some("..".to_owned()));
+
+    let partial: CommandBuilder<command_builder_state::Set, command_builder_state::Unset> =
+        Command::builder().executable("rustc".to_owned());
+    let command = partial.env(vec!["RUST_LOG=debug".to_owned()]).build();
+    assert_eq!(command.executable, "rustc");
+    assert!(command.current_dir.is_none());
+}
@@ -0,0 +1,78 @@
+// Fields marked #[builder(default)] no longer need to be set before calling
+// build; if they were never set they are filled in with Default::default().
+// #[builder(default = "...")] does the same using an arbitrary expression
+// parsed from the string. Putting #[builder(default)] on the struct itself
+// makes every field fall back to the value in the struct's own Default impl.
+
+use derive_builder::Builder;
+
+fn default_executable() -> String {
+    "cargo".to_owned()
+}
+
+#[derive(Builder)]
+pub struct Command {
+    #[builder(default = "default_executable()")]
+    executable: String,
+    #[builder(default)]
+    args: Vec<String>,
+    #[builder(default = "Some(\"..\".to_owned())")]
+    current_dir: Option<String>,
+    timeout: u32,
+}
+
+#[derive(Builder)]
+#[builder(default)]
+pub struct Server {
+    host: String,
+    port: u16,
+    #[builder(default = "4")]
+    workers: usize,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Server {
+            host: "localhost".to_owned(),
+            port: 8080,
+            workers: 1,
+        }
+    }
+}
+
+#[derive(Builder)]
+#[builder(typestate)]
+pub struct Job {
+    name: String,
+    #[builder(default = "3")]
+    retries: u32,
+}
+
+fn main() {
+    let command = Command::builder().timeout(10).build().unwrap();
+    assert_eq!(command.executable, "cargo");
+    assert!(command.args.is_empty());
+    assert_eq!(command.current_dir, Some("..".to_owned()));
+    assert_eq!(command.timeout, 10);
+
+    let command = Command::builder()
+        .executable("rustc".to_owned())
+        .current_dir("/tmp".to_owned())
+        .timeout(5)
+        .build()
+        .unwrap();
+    assert_eq!(command.executable, "rustc");
+    assert_eq!(command.current_dir, Some("/tmp".to_owned()));
+
+    let err = Command::builder().build().err().unwrap();
+    assert_eq!(err, CommandBuilderError::MissingTimeout);
+
+    let server = Server::builder().port(9000).build().unwrap();
+    assert_eq!(server.host, "localhost");
+    assert_eq!(server.port, 9000);
+    assert_eq!(server.workers, 4);
+
+    let job = Job::builder().name("nightly".to_owned()).build();
+    assert_eq!(job.name, "nightly");
+    assert_eq!(job.retries, 3);
+}
@@ -0,0 +1,88 @@
+// The builder carries over the struct's lifetimes, type parameters, const
+// parameters and where clause, so generic structs get a generic builder.
+//
+//     impl<'a, T: Transport> Conn<'a, T> where T: Clone {
+//         pub fn builder() -> ConnBuilder<'a, T> {
+//             ...
+//         }
+//     }
+//
+// Since `build` clones every field out of the builder, it is only available
+// when the field types are Clone.
+
+use derive_builder::Builder;
+use std::fmt::Debug;
+
+pub trait Transport {
+    fn name(&self) -> String;
+}
+
+#[derive(Clone)]
+pub struct Tcp;
+
+impl Transport for Tcp {
+    fn name(&self) -> String {
+        "tcp".to_owned()
+    }
+}
+
+#[derive(Builder)]
+pub struct Conn<'a, T: Transport>
+where
+    T: Clone,
+{
+    host: &'a str,
+    transport: T,
+    #[builder(each = "tag")]
+    tags: Vec<&'a str>,
+    timeout: Option<u32>,
+}
+
+#[derive(Builder)]
+pub struct Buffer<T, const N: usize> {
+    data: [T; N],
+    label: Option<String>,
+}
+
+#[derive(Builder, Default)]
+#[builder(default)]
+pub struct Pair<A, B = u8> {
+    first: A,
+    second: B,
+}
+
+#[derive(Builder)]
+#[builder(typestate)]
+pub struct Tagged<'a, T = String>
+where
+    T: Debug,
+{
+    name: &'a str,
+    value: T,
+}
+
+fn main() {
+    let host = String::from("localhost");
+    let conn = Conn::builder()
+        .host(&host)
+        .transport(Tcp)
+        .tag("fast")
+        .build()
+        .unwrap();
+    assert_eq!(conn.host, "localhost");
+    assert_eq!(conn.transport.name(), "tcp");
+    assert_eq!(conn.tags, vec!["fast"]);
+    assert!(conn.timeout.is_none());
+
+    let buffer = Buffer::builder().data([1u8, 2, 3]).build().unwrap();
+    assert_eq!(buffer.data.len(), 3);
+    assert!(buffer.label.is_none());
+
+    let pair: Pair<String> = Pair::builder().first("one".to_owned()).build().unwrap();
+    assert_eq!(pair.first, "one");
+    assert_eq!(pair.second, 0);
+
+    let tagged = Tagged::builder().value(7).name("seven").build();
+    assert_eq!(tagged.name, "seven");
+    assert_eq!(tagged.value, 7);
+}
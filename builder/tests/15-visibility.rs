@@ -0,0 +1,60 @@
+// The builder, its error type, setters and build method take the visibility
+// of the struct they are derived for, so a builder derived in one module can
+// be used from another, and a private struct does not leak a public builder.
+//
+// #[builder(vis = "...")] overrides this for the whole builder or, on a field,
+// for that field's setter. #[builder(private)] on a field keeps its setter
+// private to the module which derived the builder.
+
+mod config {
+    use derive_builder::Builder;
+
+    #[derive(Builder)]
+    pub struct Server {
+        pub host: String,
+        #[builder(private)]
+        pub port: u16,
+        #[builder(vis = "pub(crate)")]
+        pub workers: usize,
+    }
+
+    impl ServerBuilder {
+        pub fn local(&mut self) -> &mut Self {
+            self.host("localhost".to_owned()).port(8080)
+        }
+    }
+
+    #[derive(Builder)]
+    #[builder(vis = "pub(crate)")]
+    pub struct Internal {
+        pub name: String,
+    }
+
+    #[derive(Builder)]
+    struct Hidden {
+        id: u32,
+    }
+
+    pub fn hidden_id() -> u32 {
+        Hidden::builder().id(3).build().unwrap().id
+    }
+}
+
+fn main() {
+    let server = config::Server::builder()
+        .local()
+        .workers(4)
+        .build()
+        .unwrap();
+    assert_eq!(server.host, "localhost");
+    assert_eq!(server.port, 8080);
+    assert_eq!(server.workers, 4);
+
+    let mut builder: config::InternalBuilder = config::Internal::builder();
+    let err: config::InternalBuilderError = builder.build().err().unwrap();
+    assert_eq!(err.to_string(), "Missing field name");
+    let internal = builder.name("internal".to_owned()).build().unwrap();
+    assert_eq!(internal.name, "internal");
+
+    assert_eq!(config::hidden_id(), 3);
+}
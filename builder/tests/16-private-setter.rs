@@ -0,0 +1,20 @@
+// A setter marked #[builder(private)] cannot be called from outside the
+// module in which the builder was derived.
+
+mod config {
+    use derive_builder::Builder;
+
+    #[derive(Builder)]
+    pub struct Server {
+        pub host: String,
+        #[builder(private)]
+        pub port: u16,
+    }
+}
+
+fn main() {
+    let _server = config::Server::builder()
+        .host("localhost".to_owned())
+        .port(8080)
+        .build();
+}
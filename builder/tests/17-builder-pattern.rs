@@ -0,0 +1,67 @@
+// #[builder(pattern = "...")] picks how setters and build treat the builder.
+//
+// The default "mutable" pattern takes and returns `&mut Self` and clones the
+// fields out in `build`. The "owned" pattern takes and returns the builder by
+// value and `build(self)` moves the fields out, so no field needs to be Clone.
+// The "immutable" pattern takes `&self` and returns an updated copy, which
+// makes it easy to derive several builders from a common base.
+
+use derive_builder::Builder;
+
+pub struct Handle(u32);
+
+#[derive(Builder)]
+#[builder(pattern = "owned")]
+pub struct Worker {
+    handle: Handle,
+    #[builder(each = "job")]
+    jobs: Vec<Handle>,
+    name: Option<String>,
+}
+
+#[derive(Builder)]
+#[builder(pattern = "immutable")]
+pub struct Request<T: Clone> {
+    method: String,
+    body: T,
+    #[builder(each = "header")]
+    headers: Vec<String>,
+}
+
+fn main() {
+    let worker = Worker::builder()
+        .handle(Handle(1))
+        .job(Handle(2))
+        .job(Handle(3))
+        .build()
+        .unwrap();
+    assert_eq!(worker.handle.0, 1);
+    assert_eq!(worker.jobs.iter().map(|h| h.0).sum::<u32>(), 5);
+    assert!(worker.name.is_none());
+
+    let err = Worker::builder()
+        .name("idle".to_owned())
+        .build()
+        .err()
+        .unwrap();
+    assert_eq!(err, WorkerBuilderError::MissingHandle);
+
+    let base = Request::builder()
+        .method("POST".to_owned())
+        .header("Accept: */*".to_owned());
+    let small = base.body(1u8).build().unwrap();
+    let large = base
+        .body(255u8)
+        .header("X-Large: yes".to_owned())
+        .build()
+        .unwrap();
+    assert_eq!(small.method, "POST");
+    assert_eq!(small.body, 1);
+    assert_eq!(small.headers.len(), 1);
+    assert_eq!(large.body, 255);
+    assert_eq!(large.headers.len(), 2);
+    assert_eq!(
+        base.build().err().unwrap(),
+        RequestBuilderError::MissingBody
+    );
+}
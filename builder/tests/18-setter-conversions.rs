@@ -0,0 +1,88 @@
+// #[builder(setter(...))] controls the argument type of the setters, either
+// for the whole struct or for a single field.
+//
+// With `setter(into)` a setter accepts anything which is `Into` the field type.
+// With `setter(as_ref)` a setter for a `String` accepts anything which is
+// `AsRef<str>`, including `&str`, `&String` and `Cow<str>`. A struct wide
+// `setter(as_ref)` only changes the `String` setters.
+//
+// Setters for `Option<T>` fields take a plain `T` by default. Opting out with
+// `setter(strip_option = false)` makes them take the `Option<T>` itself, so
+// the caller can pass along an optional value they already have.
+
+use derive_builder::Builder;
+use std::borrow::Cow;
+use std::path::PathBuf;
+
+#[derive(Builder)]
+#[builder(setter(into))]
+pub struct Command {
+    executable: String,
+    #[builder(each = "arg")]
+    args: Vec<String>,
+    current_dir: Option<PathBuf>,
+    #[builder(setter(into = false))]
+    retries: u8,
+}
+
+#[derive(Builder)]
+#[builder(setter(as_ref))]
+pub struct Greeting {
+    name: String,
+    #[builder(each = "word")]
+    words: Vec<String>,
+    count: usize,
+    #[builder(setter(strip_option = false))]
+    suffix: Option<String>,
+}
+
+#[derive(Builder)]
+#[builder(typestate)]
+pub struct Label {
+    #[builder(setter(into))]
+    text: String,
+    #[builder(setter(strip_option = false))]
+    color: Option<u32>,
+}
+
+fn main() {
+    let command = Command::builder()
+        .executable("cargo")
+        .arg("build")
+        .arg(String::from("--release"))
+        .current_dir("/tmp")
+        .retries(3)
+        .build()
+        .unwrap();
+    assert_eq!(command.executable, "cargo");
+    assert_eq!(command.args, vec!["build", "--release"]);
+    assert_eq!(command.current_dir, Some(PathBuf::from("/tmp")));
+    assert_eq!(command.retries, 3);
+
+    let name = String::from("world");
+    let greeting = Greeting::builder()
+        .name(&name)
+        .word("hello")
+        .word(Cow::Borrowed("there"))
+        .count(2)
+        .suffix(None)
+        .build()
+        .unwrap();
+    assert_eq!(greeting.name, "world");
+    assert_eq!(greeting.words, vec!["hello", "there"]);
+    assert_eq!(greeting.count, 2);
+    assert_eq!(greeting.suffix, None);
+
+    let greeting = Greeting::builder()
+        .name("you")
+        .count(1)
+        .suffix(Some(String::from("!")))
+        .build()
+        .unwrap();
+    assert_eq!(greeting.suffix.as_deref(), Some("!"));
+
+    let color = None;
+    let label = Label::builder().text("ok").color(color).build();
+    assert_eq!(label.text, "ok");
+    assert_eq!(label.color, None);
+}
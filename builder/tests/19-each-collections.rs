@@ -0,0 +1,99 @@
+// #[builder(each = "...")] works with any collection which implements
+// `Default` and `Extend`, taking the item type from the collection's first
+// type argument, or for a type named `...Map` the key and value types from
+// its first two.  When that guess is wrong the types are given explicitly,
+// with #[builder(each(name = "...", item = "..."))] or, for a map,
+// #[builder(each(name = "...", key = "...", value = "..."))].
+//
+// Maps are filled with a setter taking a key and a value. Every `each` field
+// also gets an `extend_<field>` method adding all the items of an iterator.
+
+use derive_builder::Builder;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+
+#[derive(Clone, Default)]
+pub struct Bag<T> {
+    items: Vec<T>,
+}
+
+impl<T> Extend<T> for Bag<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        self.items.extend(iter);
+    }
+}
+
+// A fixed capacity collection, whose type argument isn't its item type
+#[derive(Clone, Default)]
+pub struct Inline<A> {
+    items: Vec<u8>,
+    _array: std::marker::PhantomData<A>,
+}
+
+impl<A> Extend<u8> for Inline<A> {
+    fn extend<I: IntoIterator<Item = u8>>(&mut self, iter: I) {
+        self.items.extend(iter);
+    }
+}
+
+// A map by another name
+pub type Dict<K, V> = Vec<(K, V)>;
+
+#[derive(Builder)]
+pub struct Request {
+    #[builder(each = "header")]
+    headers: HashMap<String, String>,
+    #[builder(each = "tag")]
+    tags: HashSet<String>,
+    #[builder(each = "param", setter(into))]
+    params: BTreeMap<String, String>,
+    #[builder(each = "step")]
+    steps: VecDeque<u32>,
+    #[builder(each = "item")]
+    items: Bag<u8>,
+    #[builder(each(name = "byte", item = "u8"))]
+    bytes: Inline<[u8; 4]>,
+    #[builder(each(name = "cookie", key = "String", value = "u32"))]
+    cookies: Dict<String, u32>,
+}
+
+#[derive(Builder)]
+#[builder(typestate)]
+pub struct Route {
+    path: String,
+    #[builder(each = "method")]
+    methods: HashSet<&'static str>,
+}
+
+fn main() {
+    let request = Request::builder()
+        .header("Host".to_owned(), "example.com".to_owned())
+        .extend_headers(vec![("Accept".to_owned(), "*/*".to_owned())])
+        .tag("a".to_owned())
+        .tag("a".to_owned())
+        .param("q", "rust")
+        .step(1)
+        .extend_steps(2..4)
+        .item(7)
+        .extend_items(vec![8, 9])
+        .byte(1)
+        .extend_bytes(vec![2])
+        .cookie("id".to_owned(), 7)
+        .build()
+        .unwrap();
+    assert_eq!(request.headers.len(), 2);
+    assert_eq!(request.headers["Host"], "example.com");
+    assert_eq!(request.tags.len(), 1);
+    assert_eq!(request.params["q"], "rust");
+    assert_eq!(request.steps, VecDeque::from(vec![1, 2, 3]));
+    assert_eq!(request.items.items, vec![7, 8, 9]);
+    assert_eq!(request.bytes.items, vec![1, 2]);
+    assert_eq!(request.cookies, vec![("id".to_owned(), 7)]);
+
+    let route = Route::builder()
+        .method("GET")
+        .extend_methods(vec!["HEAD", "GET"])
+        .path("/".to_owned())
+        .build();
+    assert_eq!(route.path, "/");
+    assert_eq!(route.methods.len(), 2);
+}
@@ -0,0 +1,100 @@
+// #[builder(build_fn(...))] customizes the generated build method.
+//
+// `validate` names a function which is given the builder once every required
+// field is set, before the struct is built. Its error converts into the build
+// error with `From`, so returning a `String` shows up as the `Validation`
+// variant of the generated error type.
+//
+// `name` renames the build method and `error` picks the error it returns
+// instead of the generated one. That type needs a `From` impl for the
+// generated error so missing fields can still be reported.
+
+use derive_builder::Builder;
+
+#[derive(Builder, Debug)]
+#[builder(build_fn(validate = "ServerBuilder::check"))]
+pub struct Server {
+    host: String,
+    port: u16,
+    tls: bool,
+    insecure: bool,
+}
+
+impl ServerBuilder {
+    fn check(&self) -> Result<(), String> {
+        if self.port == Some(0) {
+            return Err("port must not be zero".to_owned());
+        }
+        if self.tls == Some(true) && self.insecure == Some(true) {
+            return Err("tls and insecure are mutually exclusive".to_owned());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ConfigError {
+    Incomplete(String),
+    OutOfRange(u32),
+}
+
+impl From<ConfigBuilderError> for ConfigError {
+    fn from(err: ConfigBuilderError) -> Self {
+        ConfigError::Incomplete(err.to_string())
+    }
+}
+
+fn check_config(builder: &ConfigBuilder) -> Result<(), ConfigError> {
+    match builder.workers {
+        Some(n) if n > 64 => Err(ConfigError::OutOfRange(n)),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Builder, Debug)]
+#[builder(
+    pattern = "owned",
+    build_fn(name = "finish", error = "ConfigError", validate = "check_config")
+)]
+pub struct Config {
+    workers: u32,
+}
+
+fn main() {
+    let server = Server::builder()
+        .host("localhost".to_owned())
+        .port(8080)
+        .tls(true)
+        .insecure(false)
+        .build()
+        .unwrap();
+    assert_eq!(server.port, 8080);
+
+    let err = Server::builder()
+        .host("localhost".to_owned())
+        .port(443)
+        .tls(true)
+        .insecure(true)
+        .build()
+        .unwrap_err();
+    assert_eq!(
+        err,
+        ServerBuilderError::Validation("tls and insecure are mutually exclusive".to_owned())
+    );
+
+    // Missing fields are reported before validation runs.
+    let err = Server::builder().port(0).build().unwrap_err();
+    assert_eq!(err, ServerBuilderError::MissingHost);
+
+    let config = Config::builder().workers(4).finish().unwrap();
+    assert_eq!(config.workers, 4);
+
+    let err = Config::builder().workers(100).finish().unwrap_err();
+    assert_eq!(err, ConfigError::OutOfRange(100));
+
+    let err = Config::builder().finish().unwrap_err();
+    assert_eq!(
+        err,
+        ConfigError::Incomplete("Missing field workers".to_owned())
+    );
+}
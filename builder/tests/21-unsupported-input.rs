@@ -0,0 +1,16 @@
+// Builder only makes sense for types with fields to set. Anything else
+// is reported as an error pointing at the offending item rather than a panic
+// inside the macro.
+
+use derive_builder::Builder;
+
+#[derive(Builder)]
+pub union Bits {
+    int: u32,
+    float: f32,
+}
+
+#[derive(Builder)]
+pub struct Marker;
+
+fn main() {}
@@ -0,0 +1,37 @@
+// Every problem with the builder attributes is reported in one go, each one
+// pointing at the attribute or type at fault, so fixing them doesn't take a
+// compile per mistake.
+
+use derive_builder::Builder;
+
+#[derive(Builder)]
+#[builder(pattern = "shared", typo)]
+pub struct Command {
+    #[builder(each = "arg")]
+    executable: String,
+    #[builder(setter(into, unwrap))]
+    args: Vec<String>,
+    #[builder(default = "1 +")]
+    retries: u32,
+    #[builder]
+    current_dir: Option<String>,
+    #[builder(setter(append))]
+    env: Vec<String>,
+    #[builder(each = "not an ident")]
+    includes: Vec<String>,
+    #[builder(sub_builder(pattern = "shared"))]
+    limits: Limits,
+    #[builder(each(name = "flag"))]
+    flags: Vec<u8>,
+}
+
+#[derive(Builder)]
+#[builder(default, setter(skip))]
+pub struct Defaults {
+    #[builder(sub_builder)]
+    limits: Limits,
+}
+
+pub struct Limits;
+
+fn main() {}
@@ -0,0 +1,75 @@
+// Deriving Builder on an enum gives each variant with named fields a builder of
+// its own. `Msg::connect_builder()` starts a `MsgConnectBuilder` whose `build`
+// returns a `Msg::Connect { .. }`, with every field attribute working as it
+// does on a struct. Unit variants have nothing to build and get no builder.
+//
+// Attributes on the enum itself apply to every variant's builder.
+
+use derive_builder::Builder;
+
+#[derive(Builder, Debug, PartialEq)]
+pub enum Msg {
+    Connect {
+        host: String,
+        port: u16,
+        password: Option<String>,
+    },
+    Publish {
+        topic: String,
+        #[builder(each = "line")]
+        lines: Vec<String>,
+    },
+    Disconnect,
+}
+
+#[derive(Builder, Debug, PartialEq)]
+#[builder(typestate, setter(into))]
+pub enum Event<T> {
+    Tick { at: u64 },
+    Payload { value: T, retries: Option<u8> },
+}
+
+fn main() {
+    let msg = Msg::connect_builder()
+        .host("localhost".to_owned())
+        .port(1883)
+        .build()
+        .unwrap();
+    assert_eq!(
+        msg,
+        Msg::Connect {
+            host: "localhost".to_owned(),
+            port: 1883,
+            password: None,
+        }
+    );
+
+    let msg = Msg::publish_builder()
+        .topic("news".to_owned())
+        .line("a".to_owned())
+        .line("b".to_owned())
+        .build()
+        .unwrap();
+    assert_eq!(
+        msg,
+        Msg::Publish {
+            topic: "news".to_owned(),
+            lines: vec!["a".to_owned(), "b".to_owned()],
+        }
+    );
+
+    let err = Msg::connect_builder().port(1).build().unwrap_err();
+    assert_eq!(err, MsgConnectBuilderError::MissingHost);
+
+    let tick: Event<String> = Event::tick_builder().at(5u32).build();
+    assert_eq!(tick, Event::Tick { at: 5 });
+
+    let payload = Event::payload_builder().value("x").retries(2).build();
+    assert_eq!(
+        payload,
+        Event::Payload {
+            value: "x",
+            retries: Some(2),
+        }
+    );
+}
@@ -0,0 +1,47 @@
+// Tuple structs get a builder too. Their setters are named after the field's
+// position, `_0`, `_1` and so on, unless the field is given a name with
+// #[builder(name = "...")], which is then used for the setter and for the
+// `Missing<Name>` error variant.
+//
+// Tuple variants of an enum work the same way.
+
+use derive_builder::Builder;
+
+#[derive(Builder, Clone, Debug, PartialEq)]
+pub struct Rgb(u8, u8, u8);
+
+#[derive(Builder, Clone, Debug, PartialEq)]
+pub struct Size(
+    #[builder(name = "width")] u32,
+    #[builder(name = "height")] u32,
+);
+
+#[derive(Builder, Debug, PartialEq)]
+#[builder(setter(into))]
+pub struct Name(String);
+
+#[derive(Builder, Debug, PartialEq)]
+pub enum Shape {
+    Circle(f64),
+    Rect(#[builder(name = "size")] Size, Option<Rgb>),
+}
+
+fn main() {
+    let rgb = Rgb::builder()._0(255)._1(128)._2(0).build().unwrap();
+    assert_eq!(rgb, Rgb(255, 128, 0));
+
+    let size = Size::builder().width(3).height(4).build().unwrap();
+    assert_eq!(size, Size(3, 4));
+
+    let err = Size::builder().width(3).build().unwrap_err();
+    assert_eq!(err, SizeBuilderError::MissingHeight);
+
+    let name = Name::builder()._0("rust").build().unwrap();
+    assert_eq!(name, Name("rust".to_owned()));
+
+    let shape = Shape::rect_builder().size(size).build().unwrap();
+    assert_eq!(shape, Shape::Rect(Size(3, 4), None));
+
+    let shape = Shape::circle_builder()._0(1.5).build().unwrap();
+    assert_eq!(shape, Shape::Circle(1.5));
+}
@@ -0,0 +1,49 @@
+// An existing value can be turned back into a builder, either by cloning it
+// with `to_builder()` or by moving it with `From`/`Into`. Every field starts
+// out set to the value's, `each` collections included, so a baseline can be
+// tweaked and rebuilt.
+//
+// `to_builder` needs the struct to be Clone, but only where it is called.
+
+use derive_builder::Builder;
+
+#[derive(Builder, Clone, Debug, PartialEq)]
+pub struct Command {
+    executable: String,
+    #[builder(each = "arg")]
+    args: Vec<String>,
+    current_dir: Option<String>,
+}
+
+#[derive(Builder, Clone, Debug, PartialEq)]
+#[builder(typestate)]
+pub struct Point<T> {
+    x: T,
+    y: T,
+}
+
+fn main() {
+    let base = Command::builder()
+        .executable("cargo".to_owned())
+        .arg("build".to_owned())
+        .build()
+        .unwrap();
+
+    let release = base
+        .to_builder()
+        .arg("--release".to_owned())
+        .current_dir("/tmp".to_owned())
+        .build()
+        .unwrap();
+    assert_eq!(release.executable, "cargo");
+    assert_eq!(release.args, vec!["build", "--release"]);
+    assert_eq!(release.current_dir.as_deref(), Some("/tmp"));
+
+    let mut moved: CommandBuilder = base.clone().into();
+    assert_eq!(moved.build().unwrap(), base);
+
+    let origin = Point::builder().x(0).y(0).build();
+    let shifted = origin.to_builder().y(5).build();
+    assert_eq!(shifted, Point { x: 0, y: 5 });
+    assert_eq!(PointBuilder::from(shifted.clone()).build(), shifted);
+}
@@ -0,0 +1,61 @@
+// Builders can be layered, e.g. defaults, then a config file, then command line
+// flags. `merge` takes another builder whose set fields win over this one's,
+// while `or` keeps this builder's fields and falls back to another's for the
+// ones it left unset.
+//
+// When both sides have items for an `each` collection they are appended, with
+// the earlier layer's items first, unless the field asks for
+// #[builder(merge = "replace")].
+
+use derive_builder::Builder;
+use std::collections::HashMap;
+
+#[derive(Builder, Clone, Debug, PartialEq)]
+pub struct Config {
+    host: String,
+    port: u16,
+    user: Option<String>,
+    #[builder(each = "include")]
+    includes: Vec<String>,
+    #[builder(each = "env", merge = "replace")]
+    envs: HashMap<String, String>,
+}
+
+fn defaults() -> ConfigBuilder {
+    let mut defaults = Config::builder();
+    defaults
+        .host("localhost".to_owned())
+        .port(80)
+        .include("base.conf".to_owned())
+        .env("MODE".to_owned(), "default".to_owned());
+    defaults
+}
+
+fn file() -> ConfigBuilder {
+    let mut file = Config::builder();
+    file.port(8080)
+        .include("site.conf".to_owned())
+        .env("LOG".to_owned(), "info".to_owned());
+    file
+}
+
+fn flags() -> ConfigBuilder {
+    let mut flags = Config::builder();
+    flags.user("admin".to_owned());
+    flags
+}
+
+fn main() {
+    let mut layered = defaults();
+    layered.merge(file()).merge(flags());
+    let config = layered.build().unwrap();
+    assert_eq!(config.host, "localhost");
+    assert_eq!(config.port, 8080);
+    assert_eq!(config.user.as_deref(), Some("admin"));
+    assert_eq!(config.includes, vec!["base.conf", "site.conf"]);
+    assert_eq!(config.envs.len(), 1);
+    assert_eq!(config.envs["LOG"], "info");
+
+    let mut fallback = flags().or(file()).or(defaults());
+    assert_eq!(fallback.build().unwrap(), config);
+}
@@ -0,0 +1,52 @@
+// #[builder(derive(...))] derives traits on the builder itself, so builders
+// can be cloned, compared and printed in test failures. Every builder also
+// implements Default, giving the same empty builder as `Foo::builder()`, so
+// `Default` in the list is allowed but adds nothing.
+//
+// A partially built value can be inspected with `get_<field>()`, which returns
+// the value set so far if any, and `is_set_<field>()`.
+
+use derive_builder::Builder;
+
+#[derive(Builder)]
+#[builder(derive(Clone, Default, Debug, PartialEq))]
+pub struct Server {
+    host: String,
+    port: u16,
+    name: Option<String>,
+    #[builder(each = "alias")]
+    aliases: Vec<String>,
+}
+
+#[derive(Builder)]
+#[builder(typestate, derive(Clone, Debug, Default))]
+pub struct Point {
+    x: i32,
+    y: i32,
+}
+
+fn main() {
+    let mut builder = ServerBuilder::default();
+    assert_eq!(builder, Server::builder());
+    assert!(!builder.is_set_port());
+    assert_eq!(builder.get_port(), None);
+
+    builder.host("localhost".to_owned()).port(8080);
+    let snapshot = builder.clone();
+    builder.name("main".to_owned()).alias("www".to_owned());
+
+    assert!(builder.is_set_port());
+    assert_eq!(builder.get_port(), Some(&8080));
+    assert_eq!(builder.get_name().map(String::as_str), Some("main"));
+    assert_eq!(builder.get_aliases(), Some(&vec!["www".to_owned()]));
+    assert_ne!(builder, snapshot);
+    assert!(!snapshot.is_set_name());
+    assert!(format!("{:?}", snapshot).contains("8080"));
+
+    let half = PointBuilder::default().x(1);
+    assert_eq!(half.get_x(), Some(&1));
+    assert!(!half.is_set_y());
+    let point = half.clone().y(2).build();
+    assert_eq!((point.x, point.y), (1, 2));
+    assert!(format!("{:?}", half).contains("PointBuilder"));
+}
@@ -0,0 +1,58 @@
+// #[builder(skip)] leaves a field off the builder entirely, with `build`
+// filling it from `Default`, or from the expression given with
+// #[builder(setter(skip), default = "...")].
+//
+// A default expression can refer to the other fields of the struct by name.
+// Fields without a default are built first, then those with a default, then
+// the skipped ones, so a skipped field can be derived from any of the others.
+
+use derive_builder::Builder;
+use std::cell::RefCell;
+use std::marker::PhantomData;
+
+#[derive(Builder, Debug)]
+pub struct Endpoint<T> {
+    host: String,
+    port: u16,
+    #[builder(default = "format!(\"{}:{}\", host, port)")]
+    name: String,
+    #[builder(setter(skip), default = "format!(\"http://{}:{}/\", host, port)")]
+    url: String,
+    #[builder(skip)]
+    cache: RefCell<Vec<u8>>,
+    #[builder(skip)]
+    marker: PhantomData<T>,
+}
+
+#[derive(Builder, Debug)]
+#[builder(typestate)]
+pub struct Rect {
+    width: u32,
+    height: u32,
+    #[builder(setter(skip), default = "width * height")]
+    area: u32,
+}
+
+fn main() {
+    let endpoint = Endpoint::<()>::builder()
+        .host("localhost".to_owned())
+        .port(8080)
+        .build()
+        .unwrap();
+    assert_eq!(endpoint.name, "localhost:8080");
+    assert_eq!(endpoint.url, "http://localhost:8080/");
+    assert!(endpoint.cache.borrow().is_empty());
+    let PhantomData = endpoint.marker;
+
+    let endpoint = Endpoint::<()>::builder()
+        .host("example.com".to_owned())
+        .port(443)
+        .name("secure".to_owned())
+        .build()
+        .unwrap();
+    assert_eq!(endpoint.name, "secure");
+    assert_eq!(endpoint.url, "http://example.com:443/");
+
+    let rect = Rect::builder().width(3).height(4).build();
+    assert_eq!(rect.area, 12);
+}
@@ -0,0 +1,147 @@
+// #[builder(sub_builder)] on a field whose type also derives Builder keeps the
+// inner builder inside the outer one.  The method named after the field hands
+// out `&mut` to the inner builder, so nested config can be set in place.
+//
+// `build` builds the inner builder too, wrapping its error in an
+// `Invalid<Field>` variant of the outer error.  A sub-builder that was never
+// touched is built from its defaults, which only fails if the inner struct has
+// required fields.  The inner builder is built in place, so it needn't be
+// `Clone` even in a generic struct.
+//
+// The inner struct's builder options can't be seen from the outer struct, so
+// an inner builder with the owned pattern or a custom `build_fn` repeats them
+// in #[builder(sub_builder(pattern = "...", build_fn(...)))]. A `&mut` builder
+// clones an owned inner builder to build it, and a custom inner error needs
+// the same traits as the outer error.
+
+use derive_builder::Builder;
+use std::error::Error;
+
+#[derive(Builder, Debug, PartialEq)]
+pub struct TlsConfig {
+    cert: String,
+    #[builder(default)]
+    verify: bool,
+}
+
+#[derive(Builder, Debug, PartialEq)]
+pub struct Limits {
+    #[builder(default = "64")]
+    connections: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PoolError(String);
+
+impl std::fmt::Display for PoolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for PoolError {}
+
+impl From<PoolBuilderError> for PoolError {
+    fn from(err: PoolBuilderError) -> Self {
+        PoolError(err.to_string())
+    }
+}
+
+#[derive(Builder, Debug, PartialEq)]
+#[builder(
+    pattern = "owned",
+    derive(Clone),
+    build_fn(name = "finish", error = "PoolError")
+)]
+pub struct Pool {
+    size: u32,
+}
+
+#[derive(Builder, Debug, PartialEq)]
+pub struct Database {
+    #[builder(sub_builder(pattern = "owned", build_fn(name = "finish", error = "PoolError")))]
+    pool: Pool,
+}
+
+#[derive(Builder, Debug, PartialEq)]
+pub struct Wrapper(#[builder(sub_builder)] Limits);
+
+#[derive(Builder, Debug, PartialEq)]
+pub struct Tagged<T> {
+    tag: T,
+    #[builder(sub_builder)]
+    limits: Limits,
+}
+
+#[derive(Builder, Debug, PartialEq)]
+pub struct Server {
+    host: String,
+    #[builder(sub_builder)]
+    tls: TlsConfig,
+    #[builder(sub_builder)]
+    limits: Limits,
+}
+
+fn main() {
+    let mut builder = Server::builder();
+    builder.host("example.com".to_owned());
+    builder.tls().cert("server.pem".to_owned()).verify(true);
+    assert!(builder.get_tls().is_some());
+    assert!(!builder.is_set_limits());
+
+    let server = builder.build().unwrap();
+    assert_eq!(
+        server,
+        Server {
+            host: "example.com".to_owned(),
+            tls: TlsConfig {
+                cert: "server.pem".to_owned(),
+                verify: true,
+            },
+            limits: Limits { connections: 64 },
+        }
+    );
+
+    let err = Server::builder()
+        .host("example.com".to_owned())
+        .build()
+        .unwrap_err();
+    assert_eq!(
+        err,
+        ServerBuilderError::InvalidTls(TlsConfigBuilderError::MissingCert)
+    );
+    assert_eq!(err.to_string(), "tls: Missing field cert");
+    assert!(err.source().is_some());
+
+    // A failed build leaves an unset sub-builder unset.
+    let mut untouched = Server::builder();
+    assert!(untouched.build().is_err());
+    assert!(!untouched.is_set_tls());
+
+    // Merging layers the inner builders field by field.
+    let mut base = ServerBuilder::from(server);
+    let mut overrides = Server::builder();
+    overrides.tls().verify(false);
+    overrides.limits().connections(8);
+    let merged = base.merge(overrides).build().unwrap();
+    assert_eq!(merged.tls.cert, "server.pem");
+    assert!(!merged.tls.verify);
+    assert_eq!(merged.limits.connections, 8);
+
+    // Tuple struct fields work the same way, named `_0` and so on.
+    let mut wrapper = Wrapper::builder();
+    wrapper._0().connections(4);
+    assert_eq!(wrapper.build().unwrap(), Wrapper(Limits { connections: 4 }));
+
+    let mut database = Database::builder();
+    let err = database.build().unwrap_err();
+    assert_eq!(err.to_string(), "pool: Missing field size");
+    let pool = database.pool();
+    *pool = pool.clone().size(8);
+    assert_eq!(database.build().unwrap().pool, Pool { size: 8 });
+
+    let mut tagged = Tagged::builder();
+    tagged.tag('a').limits().connections(2);
+    let tagged = tagged.build().unwrap();
+    assert_eq!((tagged.tag, tagged.limits.connections), ('a', 2));
+}
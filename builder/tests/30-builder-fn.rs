@@ -0,0 +1,87 @@
+// #[builder] on a function generates a builder for its arguments, giving it
+// named and optional arguments.  `connect_builder()` starts one, and `call`
+// passes the arguments to `connect` once the required ones are set.
+//
+// Arguments follow the same rules as struct fields: an `Option` argument may
+// be left unset, `#[builder(each = "...")]` adds to a collection one item at
+// a time, and `#[builder(default = "...")]` fills in an unset argument.  The
+// builder is a typestate builder, so leaving out a required argument is a
+// compile error, and `call` returns whatever the function does.
+//
+// Arguments borrowing with elided lifetimes are held by the builder under
+// lifetimes of its own.
+
+use derive_builder::builder;
+use std::time::Duration;
+
+#[derive(Debug, PartialEq)]
+pub struct Conn {
+    addr: String,
+    timeout: Option<Duration>,
+    tags: Vec<String>,
+    retries: u32,
+}
+
+#[builder]
+pub fn connect(
+    host: &str,
+    port: u16,
+    timeout: Option<Duration>,
+    #[builder(each = "tag")] tags: Vec<String>,
+    #[builder(default = "3")] retries: u32,
+) -> Conn {
+    Conn {
+        addr: format!("{}:{}", host, port),
+        timeout,
+        tags,
+        retries,
+    }
+}
+
+#[builder(setter(into))]
+fn first_word(text: &str, separator: Option<char>) -> &str {
+    text.split(separator.unwrap_or(' ')).next().unwrap()
+}
+
+#[builder]
+fn parse<T: std::str::FromStr>(input: &str) -> Option<T> {
+    input.trim().parse().ok()
+}
+
+fn main() {
+    let host = String::from("example.com");
+    let conn = connect_builder()
+        .host(&host)
+        .port(443)
+        .tag("a".to_owned())
+        .tag("b".to_owned())
+        .call();
+    assert_eq!(
+        conn,
+        Conn {
+            addr: "example.com:443".to_owned(),
+            timeout: None,
+            tags: vec!["a".to_owned(), "b".to_owned()],
+            retries: 3,
+        }
+    );
+
+    let conn = connect_builder()
+        .port(80)
+        .timeout(Duration::from_secs(5))
+        .retries(0)
+        .host("localhost")
+        .call();
+    assert_eq!(conn.addr, "localhost:80");
+    assert_eq!(conn.timeout, Some(Duration::from_secs(5)));
+    assert_eq!(conn.retries, 0);
+
+    // The function itself is still there to call directly.
+    assert_eq!(connect("a", 1, None, Vec::new(), 0).addr, "a:1");
+
+    let text = String::from("hello,world");
+    let word = first_word_builder().text(&*text).separator(',').call();
+    assert_eq!(word, "hello");
+
+    assert_eq!(parse_builder().input(" 42 ").call(), Some(42u8));
+}
@@ -0,0 +1,31 @@
+// A function builder holds the arguments as fields, so each needs a name and
+// a type it can store.  A method's builder can't be declared inside its impl
+// block, so `#[builder]` goes on the impl too, and that impl must be an
+// inherent one to add the methods starting the builders.
+
+use derive_builder::builder;
+
+pub struct Client;
+
+impl Client {
+    #[builder]
+    pub fn send(&self, path: &str) {}
+
+    #[builder]
+    pub fn new(name: &str) -> Self {
+        Client
+    }
+}
+
+#[builder]
+pub fn log(message: impl Into<String>, (line, column): (u32, u32)) {}
+
+#[builder]
+impl Clone for Client {
+    #[builder]
+    fn clone(&self) -> Self {
+        Client
+    }
+}
+
+fn main() {}
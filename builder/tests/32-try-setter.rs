@@ -0,0 +1,66 @@
+// #[builder(try_setter)] adds `try_<field>` next to the usual setter, taking
+// anything that converts to the field's type with `TryInto` and returning the
+// conversion error, rather than the builder, if it fails.  Putting it on the
+// struct gives every field one.
+//
+// With a typestate builder a successful `try_` setter moves into the state
+// where the field is set, just like the plain setter does.
+
+use derive_builder::Builder;
+use std::convert::TryFrom;
+use std::num::{NonZeroU32, TryFromIntError};
+
+#[derive(Builder, Debug)]
+pub struct Listener {
+    #[builder(try_setter)]
+    port: u16,
+    #[builder(try_setter)]
+    backlog: Option<NonZeroU32>,
+    host: String,
+}
+
+#[derive(Builder, Debug)]
+#[builder(try_setter, pattern = "owned")]
+pub struct Limits {
+    connections: u8,
+    #[builder(default = "1")]
+    threads: u8,
+}
+
+#[derive(Builder, Debug)]
+#[builder(typestate)]
+pub struct Window {
+    #[builder(try_setter)]
+    width: u16,
+    #[builder(try_setter)]
+    height: u16,
+}
+
+fn main() -> Result<(), TryFromIntError> {
+    let mut builder = Listener::builder();
+    builder.try_port(8080i32)?.try_backlog(128u32)?;
+    builder.host("localhost".to_owned());
+    let listener = builder.build().unwrap();
+    assert_eq!(listener.port, 8080);
+    assert_eq!(listener.backlog, NonZeroU32::new(128));
+
+    assert!(Listener::builder().try_port(70000u32).is_err());
+    assert!(Listener::builder().try_backlog(0u32).is_err());
+
+    let limits = Limits::builder()
+        .try_connections(100u64)?
+        .try_threads(4i64)?
+        .build()
+        .unwrap();
+    assert_eq!((limits.connections, limits.threads), (100, 4));
+    match Limits::builder().try_connections(300) {
+        Err(err) => assert_eq!(err, u8::try_from(300).unwrap_err()),
+        Ok(_) => panic!("300 fits in a u8"),
+    }
+
+    let window = Window::builder().try_width(640u64)?.height(480).build();
+    assert_eq!((window.width, window.height), (640, 480));
+    assert!(Window::builder().width(1).try_height(-1).is_err());
+
+    Ok(())
+}
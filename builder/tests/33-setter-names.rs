@@ -0,0 +1,62 @@
+// #[builder(setter(prefix = "with"))] on the struct names every setter
+// `with_<field>`, leaving plain field names free for getters on the built
+// type, and #[builder(setter(name = "..."))] names a single field's setter.
+// `try_` setters follow, as `try_with_port` and so on.
+//
+// Doc comments on a field are copied onto its setter, and `build` gets a doc
+// comment of its own listing the required fields.
+
+use derive_builder::Builder;
+
+#[derive(Builder, Debug)]
+#[builder(setter(prefix = "with"))]
+pub struct Server {
+    /// Host name or address to bind to.
+    host: String,
+    /// TCP port, which has to fit in a `u16`.
+    #[builder(try_setter)]
+    port: u16,
+    /// Extra headers sent with every response.
+    #[builder(each = "header")]
+    headers: Vec<String>,
+    #[builder(setter(name = "workers"))]
+    worker_threads: Option<usize>,
+}
+
+impl Server {
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+}
+
+#[derive(Builder, Debug)]
+#[builder(typestate)]
+pub struct Point {
+    #[builder(setter(name = "at_x"))]
+    x: i32,
+    #[builder(setter(prefix = "with"))]
+    y: i32,
+}
+
+fn main() {
+    let mut builder = Server::builder();
+    builder
+        .with_host("localhost".to_owned())
+        .try_with_port(8080u32)
+        .unwrap()
+        .header("Server: test".to_owned())
+        .with_headers(vec!["X-A: 1".to_owned()])
+        .workers(4);
+    let server = builder.build().unwrap();
+    assert_eq!(server.host(), "localhost");
+    assert_eq!(server.port(), 8080);
+    assert_eq!(server.headers, ["X-A: 1"]);
+    assert_eq!(server.worker_threads, Some(4));
+
+    let point = Point::builder().with_y(2).at_x(1).build();
+    assert_eq!((point.x, point.y), (1, 2));
+}
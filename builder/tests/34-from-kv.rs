@@ -0,0 +1,89 @@
+// #[builder(from_kv)] lets a builder be filled from strings, as read from
+// `key=value` files or the environment.  `set_from_str(key, value)` parses the
+// value with `FromStr` into the field named by the key, ignoring ASCII case,
+// or the field given that key with #[builder(key = "...")].  An `each` field
+// takes one item per key, or `k=v` for a map.
+//
+// `from_env(prefix)` starts a builder from the environment variables named by
+// the prefix and the upper case key, with `each` fields split on commas, and
+// a sub-builder reading the variables under `<PREFIX><KEY>_`.
+//
+// Unknown keys and values which don't parse are errors of their own.
+
+use derive_builder::Builder;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+
+#[derive(Builder, Debug, PartialEq)]
+#[builder(from_kv)]
+pub struct Tls {
+    cert: String,
+    #[builder(default)]
+    verify: bool,
+}
+
+#[derive(Builder, Debug, PartialEq)]
+#[builder(from_kv)]
+pub struct Config {
+    #[builder(key = "bind")]
+    address: IpAddr,
+    port: u16,
+    workers: Option<usize>,
+    #[builder(each = "tag")]
+    tags: Vec<String>,
+    #[builder(each = "label")]
+    labels: BTreeMap<String, u32>,
+    #[builder(sub_builder)]
+    tls: Tls,
+}
+
+fn main() {
+    let mut builder = Config::builder();
+    for line in "bind=127.0.0.1\nPORT=8080\ntags=a\ntags=b\nlabels=x=1\ntls.cert=c.pem".lines() {
+        let mut parts = line.splitn(2, '=');
+        let (key, value) = (parts.next().unwrap(), parts.next().unwrap());
+        builder.set_from_str(key, value).unwrap();
+    }
+    let config = builder.build().unwrap();
+    assert_eq!(config.address, IpAddr::from([127, 0, 0, 1]));
+    assert_eq!(config.port, 8080);
+    assert_eq!(config.workers, None);
+    assert_eq!(config.tags, ["a", "b"]);
+    assert_eq!(config.labels.get("x"), Some(&1));
+    assert_eq!(config.tls.cert, "c.pem");
+
+    let mut builder = Config::builder();
+    assert_eq!(
+        builder.set_from_str("colour", "red"),
+        Err(ConfigBuilderError::UnknownKey("colour".to_owned()))
+    );
+    let err = builder.set_from_str("port", "http").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Invalid value for port: invalid digit found in string"
+    );
+    assert!(builder.set_from_str("labels", "missing").is_err());
+
+    std::env::set_var("APP_BIND", "::1");
+    std::env::set_var("APP_PORT", "443");
+    std::env::set_var("APP_WORKERS", "8");
+    std::env::set_var("APP_TAGS", "x,y");
+    std::env::set_var("APP_TLS_CERT", "server.pem");
+    std::env::set_var("APP_TLS_VERIFY", "true");
+    let config = ConfigBuilder::from_env("APP_").unwrap().build().unwrap();
+    assert_eq!(config.address, "::1".parse::<IpAddr>().unwrap());
+    assert_eq!(config.port, 443);
+    assert_eq!(config.workers, Some(8));
+    assert_eq!(config.tags, ["x", "y"]);
+    assert!(config.labels.is_empty());
+    assert_eq!(
+        config.tls,
+        Tls {
+            cert: "server.pem".to_owned(),
+            verify: true,
+        }
+    );
+
+    std::env::set_var("BAD_PORT", "-1");
+    assert!(ConfigBuilder::from_env("BAD_").is_err());
+}
@@ -0,0 +1,81 @@
+// The expansion names everything it can through `::core`, so builders work in
+// `#![no_std]` crates.  Only the error type needs more than `core`: it
+// implements `std::error::Error` and validation fails with a `String`.  With
+// #[builder(no_std)] it implements `core::error::Error` instead, and
+// validation fails with a `&'static str`.  `each` collections come from
+// `alloc` like any other collection would.
+//
+// This test is `no_std` but links `std` under another name to run `main`, so
+// any path through `std` in the expansion fails to resolve.
+
+#![no_std]
+
+extern crate alloc;
+extern crate std as host;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use derive_builder::Builder;
+
+#[derive(Builder, Debug, PartialEq)]
+#[builder(no_std, build_fn(validate = "Self::check"))]
+pub struct Sensor {
+    #[builder(setter(as_ref))]
+    name: String,
+    #[builder(try_setter)]
+    address: u8,
+    rate: Option<u32>,
+    #[builder(each = "channel")]
+    channels: Vec<u8>,
+    #[builder(sub_builder)]
+    calibration: Calibration,
+}
+
+impl SensorBuilder {
+    fn check(&self) -> Result<(), &'static str> {
+        match self.address {
+            Some(0) => Err("address 0 is reserved"),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Builder, Debug, PartialEq)]
+#[builder(no_std)]
+pub struct Calibration {
+    #[builder(default = "1")]
+    scale: i32,
+}
+
+#[derive(Builder, Debug)]
+#[builder(typestate, pattern = "owned")]
+pub struct Pin {
+    number: u8,
+    #[builder(default)]
+    inverted: bool,
+}
+
+fn main() {
+    let mut builder = Sensor::builder();
+    builder
+        .name("thermo")
+        .try_address(0x48u32)
+        .unwrap()
+        .channel(1)
+        .channel(2);
+    builder.calibration().scale(3);
+    let sensor = builder.build().unwrap();
+    assert_eq!(sensor.name, "thermo");
+    assert_eq!(sensor.address, 0x48);
+    assert_eq!(sensor.rate, None);
+    assert_eq!(sensor.channels, [1, 2]);
+    assert_eq!(sensor.calibration.scale, 3);
+
+    let err = Sensor::builder().name("x").address(0).build().unwrap_err();
+    assert_eq!(err, SensorBuilderError::Validation("address 0 is reserved"));
+    let err: &dyn core::error::Error = &SensorBuilderError::MissingName;
+    host::assert_eq!(host::format!("{}", err), "Missing field name");
+
+    let pin = Pin::builder().number(4).build();
+    assert_eq!((pin.number, pin.inverted), (4, false));
+}
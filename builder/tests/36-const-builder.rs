@@ -0,0 +1,55 @@
+// #[builder(const)] makes `builder`, the setters and `build` `const fn`s, so a
+// builder can fill in a `static` or `const`.  It always uses the owned
+// pattern, and since `build` can't return an error it returns the struct
+// itself, with a missing required field panicking with a message naming it.
+// In a const that panic is a compile error.
+//
+// A const fn can't call trait methods, so fields are stored as given, and the
+// options which need `Into`, `Extend` or `Default` aren't available.  A
+// default or skipped field needs its value given as `default = "..."`.
+
+use derive_builder::Builder;
+
+#[derive(Builder, Debug, PartialEq)]
+#[builder(const)]
+pub struct Desc {
+    id: u16,
+    name: &'static str,
+    parent: Option<u16>,
+    #[builder(default = "0x10")]
+    flags: u8,
+    #[builder(default = "name.len()")]
+    name_len: usize,
+    #[builder(skip, default = "1")]
+    version: u8,
+}
+
+static DESC: Desc = Desc::builder().id(3).name("x").build();
+
+const TABLE: [Desc; 2] = [
+    Desc::builder().id(1).name("root").flags(0).build(),
+    Desc::builder().name("child").parent(1).id(2).build(),
+];
+
+fn main() {
+    assert_eq!(
+        DESC,
+        Desc {
+            id: 3,
+            name: "x",
+            parent: None,
+            flags: 0x10,
+            name_len: 1,
+            version: 1,
+        }
+    );
+    assert_eq!(TABLE[0].flags, 0);
+    assert_eq!(TABLE[1].parent, Some(1));
+    assert_eq!(TABLE[1].name_len, 5);
+
+    // At run time a missing field is a panic like any other.
+    std::panic::set_hook(Box::new(|_| {}));
+    let missing = std::panic::catch_unwind(|| Desc::builder().id(4).build());
+    let message = missing.unwrap_err();
+    assert_eq!(message.downcast_ref::<&str>(), Some(&"Missing field name"));
+}
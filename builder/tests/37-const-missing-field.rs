@@ -0,0 +1,15 @@
+// A const builder missing a required field fails the const evaluation, with
+// the panic naming the field.
+
+use derive_builder::Builder;
+
+#[derive(Builder)]
+#[builder(const)]
+pub struct Desc {
+    id: u16,
+    name: &'static str,
+}
+
+static DESC: Desc = Desc::builder().id(3).build();
+
+fn main() {}
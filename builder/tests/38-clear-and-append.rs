@@ -0,0 +1,70 @@
+// Every field gets a `clear_<field>` method unsetting it again, so `build`
+// treats it as never set.
+//
+// The whole-collection setter of an `each` field replaces any items added so
+// far, unless the field has #[builder(setter(append))], in which case it adds
+// to them. Either way the item setters and the whole-collection setter can be
+// called in any order.
+
+use derive_builder::Builder;
+use std::collections::BTreeMap;
+
+#[derive(Builder, Debug)]
+pub struct Command {
+    executable: String,
+    #[builder(each = "arg")]
+    args: Vec<String>,
+    #[builder(each = "var", setter(append))]
+    env: BTreeMap<String, String>,
+    #[builder(default = "30")]
+    timeout: u32,
+    current_dir: Option<String>,
+}
+
+#[derive(Builder, Debug)]
+#[builder(typestate)]
+pub struct Job {
+    name: &'static str,
+    #[builder(each = "step", setter(append))]
+    steps: Vec<&'static str>,
+    retries: Option<u8>,
+}
+
+fn main() {
+    let mut builder = Command::builder();
+    builder
+        .executable("cargo".to_owned())
+        .arg("build".to_owned())
+        .args(vec!["test".to_owned()])
+        .arg("--release".to_owned())
+        .var("A".to_owned(), "1".to_owned())
+        .env({
+            let mut env = BTreeMap::new();
+            env.insert("B".to_owned(), "2".to_owned());
+            env
+        })
+        .timeout(5)
+        .clear_timeout()
+        .current_dir("/tmp".to_owned())
+        .clear_current_dir();
+    let command = builder.build().unwrap();
+    assert_eq!(command.args, ["test", "--release"]);
+    assert_eq!(command.env.len(), 2);
+    assert_eq!(command.timeout, 30);
+    assert_eq!(command.current_dir, None);
+
+    builder.clear_args().clear_executable();
+    assert!(!builder.is_set_args());
+    assert!(builder.build().is_err());
+
+    let job = Job::builder()
+        .steps(vec!["fetch"])
+        .step("build")
+        .steps(vec!["test"])
+        .retries(3)
+        .clear_retries()
+        .name("ci")
+        .build();
+    assert_eq!(job.steps, ["fetch", "build", "test"]);
+    assert_eq!(job.retries, None);
+}
@@ -0,0 +1,87 @@
+// With the `serde` feature, #[builder(derive_deserialize)] implements serde's
+// `Deserialize` for the builder, so partial configs can be read straight into
+// builders, merged, and built. Every field is optional in the input, keyed by
+// the field name or #[builder(key = "...")], and fields absent from every
+// source are reported by `build` as missing.  A field renamed by
+// #[serde(rename = "...")] is read by its serde name, and `null` leaves an
+// `Option` field unset.
+//
+// An `each` collection is read whole, and a sub-builder needs
+// `derive_deserialize` of its own.
+
+use derive_builder::Builder;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+
+#[derive(Builder, Debug, PartialEq, Serialize)]
+#[builder(derive_deserialize)]
+pub struct Tls {
+    cert: String,
+    #[builder(default)]
+    verify: bool,
+}
+
+#[derive(Builder, Debug, PartialEq, Serialize)]
+#[builder(derive_deserialize)]
+pub struct Config {
+    #[builder(key = "bind")]
+    address: IpAddr,
+    #[serde(rename = "listenPort")]
+    port: u16,
+    #[builder(setter(strip_option = false))]
+    workers: Option<usize>,
+    name: Option<String>,
+    #[builder(each = "tag")]
+    tags: Vec<String>,
+    #[builder(each = "label")]
+    labels: BTreeMap<String, u32>,
+    #[builder(sub_builder)]
+    tls: Tls,
+}
+
+#[derive(Builder, Debug)]
+#[builder(derive_deserialize)]
+pub struct Pair<T> {
+    left: T,
+    right: T,
+}
+
+fn main() {
+    let file: ConfigBuilder = serde_json::from_str(include_str!("39-deserialize.json")).unwrap();
+    let flags: ConfigBuilder =
+        serde_json::from_str(r#"{"listenPort": 8080, "tags": ["c"], "name": "api"}"#).unwrap();
+
+    let mut builder = file;
+    builder.merge(flags);
+    let config = builder.build().unwrap();
+    assert_eq!(config.address, IpAddr::from([127, 0, 0, 1]));
+    assert_eq!(config.port, 8080);
+    assert_eq!(config.workers, None);
+    assert_eq!(config.name.as_deref(), Some("api"));
+    assert_eq!(config.tags, ["a", "b", "c"]);
+    assert_eq!(config.labels["x"], 1);
+    assert_eq!(
+        config.tls,
+        Tls {
+            cert: "c.pem".to_owned(),
+            verify: false,
+        }
+    );
+
+    let mut partial: ConfigBuilder = serde_json::from_str(r#"{"bind": "::1"}"#).unwrap();
+    match partial.build() {
+        Err(err) => assert_eq!(err, ConfigBuilderError::MissingPort),
+        Ok(_) => panic!("built without a port"),
+    }
+
+    let unnamed: ConfigBuilder = serde_json::from_str(r#"{"name": null}"#).unwrap();
+    assert_eq!(unnamed.name, None);
+
+    let duplicate = serde_json::from_str::<ConfigBuilder>(r#"{"listenPort": 1, "listenPort": 2}"#);
+    assert!(duplicate.is_err());
+
+    let mut pair: PairBuilder<u8> = serde_json::from_str(r#"{"left": 1, "right": 2}"#).unwrap();
+    let pair = pair.build().unwrap();
+    assert_eq!((pair.left, pair.right), (1, 2));
+}
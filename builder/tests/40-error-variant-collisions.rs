@@ -0,0 +1,28 @@
+// Every field that can fail to build gets a variant of the builder error named
+// after it. A field whose variant would clash with another variant, fixed or
+// another field's differing only in underscores, is an error pointing at the
+// field, rather than a duplicate definition.
+
+use derive_builder::Builder;
+
+#[derive(Builder, Debug)]
+#[builder(from_kv)]
+pub struct Limits {
+    #[builder(default)]
+    connections: u32,
+}
+
+#[derive(Builder, Debug)]
+#[builder(from_kv)]
+pub struct Server {
+    #[builder(sub_builder)]
+    value: Limits,
+}
+
+#[derive(Builder, Debug)]
+pub struct Route {
+    foo_bar: String,
+    foo__bar: String,
+}
+
+fn main() {}
@@ -0,0 +1,99 @@
+// #[builder] on an inherent impl block gives each method in it marked
+// #[builder] a builder, named after the type and the method.  The builder is
+// started by `<method>_builder`, on the value for a method taking `self`, and
+// on the type for an associated function.
+//
+// A borrowed receiver is held by the builder, so a method returning a borrow
+// of `self` keeps it, and `Self` in the signature means the impl's type.
+
+use derive_builder::builder;
+use std::collections::HashMap;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Client {
+    base: String,
+    sent: Vec<String>,
+}
+
+#[builder]
+impl Client {
+    #[builder]
+    pub fn new(base: &str, #[builder(default = "\"http\"")] scheme: &str) -> Self {
+        Client {
+            base: format!("{}://{}", scheme, base),
+            sent: Vec::new(),
+        }
+    }
+
+    #[builder(setter(into))]
+    pub fn url(&self, path: String, query: Option<String>) -> String {
+        match query {
+            Some(query) => format!("{}{}?{}", self.base, path, query),
+            None => format!("{}{}", self.base, path),
+        }
+    }
+
+    #[builder]
+    pub fn send(&mut self, path: &str, #[builder(each = "header")] headers: Vec<String>) -> &str {
+        self.sent.push(format!("{} {:?}", path, headers));
+        self.sent.last().unwrap()
+    }
+
+    #[builder]
+    pub fn into_sent(self, skip: Option<usize>) -> Vec<String> {
+        self.sent.into_iter().skip(skip.unwrap_or(0)).collect()
+    }
+
+    // Methods without #[builder] are left alone.
+    pub fn base(&self) -> &str {
+        &self.base
+    }
+}
+
+pub struct Cache<K, V> {
+    entries: HashMap<K, V>,
+}
+
+#[builder]
+impl<K: std::hash::Hash + Eq, V: Clone> Cache<K, V> {
+    #[builder]
+    pub fn get_or<Q: Into<K>>(&self, key: Q, fallback: V) -> V {
+        self.entries.get(&key.into()).cloned().unwrap_or(fallback)
+    }
+}
+
+fn main() {
+    let mut client = Client::new_builder().base("example.com").call();
+    assert_eq!(client.base(), "http://example.com");
+    let client2 = Client::new_builder().scheme("https").base("a.b").call();
+    assert_eq!(client2.base, "https://a.b");
+
+    let url = client.url_builder().path("/x").query("y=1").call();
+    assert_eq!(url, "http://example.com/x?y=1");
+    assert_eq!(client.url_builder().path("/x").call(), "http://example.com/x");
+
+    let sent = client
+        .send_builder()
+        .header("a".to_owned())
+        .path("/one")
+        .call();
+    assert_eq!(sent, "/one [\"a\"]");
+    client.send_builder().path("/two").call();
+
+    // The method itself is still there to call directly.
+    client.send("/three", Vec::new());
+
+    assert_eq!(
+        client.into_sent_builder().skip(1).call(),
+        vec!["/two []".to_owned(), "/three []".to_owned()]
+    );
+
+    let builder: ClientUrlBuilder<'_, _> = client2.url_builder();
+    assert_eq!(builder.path("/").call(), "https://a.b/");
+
+    let mut entries = HashMap::new();
+    entries.insert("k".to_owned(), 1);
+    let cache = Cache { entries };
+    assert_eq!(cache.get_or_builder().key("k").fallback(0).call(), 1);
+    assert_eq!(cache.get_or_builder().key("z").fallback(0).call(), 0);
+}
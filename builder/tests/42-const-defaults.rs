@@ -0,0 +1,27 @@
+// A const builder can't call `Default::default`, so a bare `default` or a
+// `skip` with no `default = "..."` to fill the field in is an error naming
+// the option.  Nor can it drop a replaced value of a generic type, so type
+// parameters are an error too.
+
+use derive_builder::Builder;
+
+#[derive(Builder)]
+#[builder(const)]
+pub struct Desc {
+    id: u16,
+    #[builder(default)]
+    flags: u8,
+    #[builder(skip)]
+    cache: Option<u32>,
+    #[builder(skip, default = "1")]
+    version: u8,
+}
+
+#[derive(Builder)]
+#[builder(const)]
+pub struct Tagged<'a, T> {
+    name: &'a str,
+    tag: T,
+}
+
+fn main() {}
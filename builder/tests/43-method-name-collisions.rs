@@ -0,0 +1,37 @@
+// Besides its setters a builder has methods of its own, like `merge`, `or` and
+// `build`, and methods named after each field, like `get_<field>`.  A field
+// whose method would clash with another is an error pointing at the field,
+// rather than a duplicate definition.  Renaming the setter is enough when it's
+// the setter that clashes.
+
+use derive_builder::Builder;
+
+#[derive(Builder)]
+pub struct GitConfig {
+    merge: bool,
+    name: String,
+    or: Option<String>,
+}
+
+#[derive(Builder)]
+pub struct Renamed {
+    #[builder(setter(name = "set_merge"))]
+    merge: bool,
+}
+
+#[derive(Builder)]
+pub struct Server {
+    port: u16,
+    get_port: u16,
+    #[builder(each = "port")]
+    ports: Vec<u16>,
+}
+
+#[derive(Builder)]
+#[builder(typestate)]
+pub struct Point {
+    x: i32,
+    build: i32,
+}
+
+fn main() {}
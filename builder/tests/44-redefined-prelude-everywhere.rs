@@ -0,0 +1,90 @@
+// Like tests/09-redefined-prelude-types.rs, but for every kind of builder and
+// option, each of which expands to code of its own.  Besides prelude types,
+// `Ok` and `Err` are shadowed by tuple structs of the same name, and standard
+// macros like `format!` by macros which refuse to expand, so generated code
+// has to reach them all through absolute paths.
+
+use derive_builder::{builder, Builder};
+use std::collections::BTreeMap;
+
+type Option = ();
+type Some = ();
+type None = ();
+type Result = ();
+type Box = ();
+pub struct Ok(u8);
+pub struct Err(u8);
+
+#[allow(unused_macros)]
+macro_rules! format {
+    ($($tt:tt)*) => {
+        compile_error!("format! is shadowed")
+    };
+}
+
+#[allow(unused_macros)]
+macro_rules! unreachable {
+    ($($tt:tt)*) => {
+        compile_error!("unreachable! is shadowed")
+    };
+}
+
+#[allow(unused_macros)]
+macro_rules! panic {
+    ($($tt:tt)*) => {
+        compile_error!("panic! is shadowed")
+    };
+}
+
+#[derive(Builder)]
+#[builder(from_kv, try_setter, setter(into))]
+pub struct Tls {
+    cert: String,
+}
+
+#[derive(Builder)]
+#[builder(from_kv)]
+pub struct Command {
+    executable: String,
+    #[builder(each = "arg")]
+    args: Vec<String>,
+    #[builder(each = "env")]
+    envs: BTreeMap<String, String>,
+    #[builder(default = "30")]
+    timeout: u32,
+    current_dir: std::option::Option<String>,
+    #[builder(sub_builder)]
+    tls: Tls,
+}
+
+#[derive(Builder, Default)]
+#[builder(pattern = "immutable", default)]
+pub struct Limits {
+    connections: u32,
+}
+
+#[derive(Builder)]
+#[builder(typestate)]
+pub struct Point {
+    x: i32,
+    #[builder(default)]
+    y: i32,
+}
+
+#[derive(Builder)]
+#[builder(const)]
+pub struct Desc {
+    id: u16,
+}
+
+#[derive(Builder)]
+pub enum Shape {
+    Circle { radius: u32 },
+}
+
+#[builder]
+pub fn connect(host: &str, #[builder(default)] port: u16) -> usize {
+    host.len() + port as usize
+}
+
+fn main() {}
@@ -0,0 +1,7 @@
+// The tests needing the `serde` feature, which cargo only runs with
+// `cargo test --features serde`.
+#[test]
+fn tests() {
+    let t = trybuild::TestCases::new();
+    t.pass("tests/39-deserialize.rs");
+}
@@ -10,4 +10,40 @@ fn tests() {
     t.pass("tests/07-repeated-field.rs");
     t.compile_fail("tests/08-unrecognized-attribute.rs");
     t.pass("tests/09-redefined-prelude-types.rs");
+    t.pass("tests/10-typed-errors.rs");
+    t.pass("tests/11-typestate.rs");
+    t.compile_fail("tests/12-typestate-missing-field.rs");
+    t.pass("tests/13-defaults.rs");
+    t.pass("tests/14-generics.rs");
+    t.pass("tests/15-visibility.rs");
+    t.compile_fail("tests/16-private-setter.rs");
+    t.pass("tests/17-builder-pattern.rs");
+    t.pass("tests/18-setter-conversions.rs");
+    t.pass("tests/19-each-collections.rs");
+    t.pass("tests/20-build-fn.rs");
+    t.compile_fail("tests/21-unsupported-input.rs");
+    t.compile_fail("tests/22-accumulated-errors.rs");
+    t.pass("tests/23-enum.rs");
+    t.pass("tests/24-tuple-struct.rs");
+    t.pass("tests/25-to-builder.rs");
+    t.pass("tests/26-merge.rs");
+    t.pass("tests/27-builder-derives.rs");
+    t.pass("tests/28-skip.rs");
+    t.pass("tests/29-sub-builder.rs");
+    t.pass("tests/30-builder-fn.rs");
+    t.compile_fail("tests/31-builder-fn-errors.rs");
+    t.pass("tests/32-try-setter.rs");
+    t.pass("tests/33-setter-names.rs");
+    t.pass("tests/34-from-kv.rs");
+    t.pass("tests/35-no-std.rs");
+    t.pass("tests/36-const-builder.rs");
+    t.compile_fail("tests/37-const-missing-field.rs");
+    t.pass("tests/38-clear-and-append.rs");
+    // tests/39-deserialize.rs needs the `serde` feature, so runs from
+    // tests/deserialize.rs.
+    t.compile_fail("tests/40-error-variant-collisions.rs");
+    t.pass("tests/41-builder-methods.rs");
+    t.compile_fail("tests/42-const-defaults.rs");
+    t.compile_fail("tests/43-method-name-collisions.rs");
+    t.pass("tests/44-redefined-prelude-everywhere.rs");
 }